@@ -0,0 +1,689 @@
+//! Long-lived process that keeps the session with KeePassXC, so that helpers don't need to exchange
+//! keys and test associations every time
+//!
+//! Helpers exchange keys with the agent as they would with KeePassXC. The agent decrypts their
+//! requests and sends them on encrypted with its own session, so neither the session key nor the
+//! association keys it has seen ever leave the agent. Only git-credential-keepassxc run by the
+//! same user, and by an allowed caller if there are caller profiles, may connect.
+
+use crate::cli;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+#[cfg(windows)]
+pub fn agent<T: AsRef<Path>>(_config_path: T, _args: &cli::SubAgentArgs) -> Result<()> {
+    Err(anyhow!("Agent is only supported on Unix-like systems"))
+}
+
+#[cfg(unix)]
+pub use self::unix::agent;
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use crate::config::{Caller, Config};
+    use crate::keepassxc::messages::*;
+    use crate::keepassxc::version::server_version;
+    use crate::utils::callers::CurrentCaller;
+    use crate::utils::json_stream::JsonStreamDecoder;
+    use crate::utils::*;
+    #[allow(unused_imports)]
+    use crate::{debug, error, info, warn};
+    use crate::{requires_caller_verification, start_session};
+    use serde::Deserialize;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use sysinfo::{Pid, PidExt, ProcessExt, RefreshKind, System, SystemExt};
+
+    const SOCKET_DIR_MODE: u32 = 0o700;
+    const SOCKET_MODE: u32 = 0o600;
+
+    /// Just enough of a request from a helper to tell what it is
+    #[derive(Deserialize, Debug)]
+    struct Envelope {
+        action: KeePassAction,
+        message: Option<String>,
+        nonce: Option<String>,
+        #[serde(rename = "publicKey")]
+        public_key: Option<String>,
+        #[serde(rename = "requestID")]
+        request_id: Option<String>,
+        #[serde(rename = "triggerUnlock", default)]
+        trigger_unlock: String,
+    }
+
+    /// Decrypted request of a helper for the thread which talks to KeePassXC, answered with the
+    /// response wrapper of which the message has been decrypted as well
+    struct Job {
+        action: KeePassAction,
+        message: Value,
+        trigger_unlock: String,
+        reply: mpsc::Sender<GenericResponseWrapper>,
+    }
+
+    /// Removes the socket when the agent exits
+    struct SocketGuard(PathBuf);
+
+    impl Drop for SocketGuard {
+        fn drop(&mut self) {
+            if let Err(e) = fs::remove_file(&self.0) {
+                warn!("Failed to remove {}: {}", self.0.to_string_lossy(), e);
+            }
+        }
+    }
+
+    /// Process ID and user ID of the other end of the socket
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_credentials(stream: &UnixStream) -> Result<(u32, u32)> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: cred and len are valid for writes of the sizes given
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok((cred.pid as u32, cred.uid))
+    }
+
+    /// Process ID and user ID of the other end of the socket
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    fn peer_credentials(stream: &UnixStream) -> Result<(u32, u32)> {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: uid and gid are valid for writes
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut pid: libc::pid_t = 0;
+        let mut len = std::mem::size_of::<libc::pid_t>() as libc::socklen_t;
+        // SAFETY: pid and len are valid for writes of the sizes given
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_LOCAL,
+                libc::LOCAL_PEERPID,
+                (&mut pid as *mut libc::pid_t).cast(),
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok((pid as u32, uid))
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    fn peer_credentials(_stream: &UnixStream) -> Result<(u32, u32)> {
+        Err(anyhow!(
+            "Retrieving peer credentials is not supported on this system"
+        ))
+    }
+
+    fn canonical_path(path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| path.to_owned())
+    }
+
+    /// Who may connect, as of when the agent started
+    struct AllowedPeers {
+        uid: u32,
+        exe: PathBuf,
+        /// None if callers don't need to be verified
+        callers: Option<Vec<Caller>>,
+    }
+
+    impl AllowedPeers {
+        /// Only the same executable run by the same user may connect, and its caller has to be
+        /// allowed like any invocation which connects to KeePassXC directly
+        fn verify(&self, stream: &UnixStream) -> Result<()> {
+            let (pid, uid) = peer_credentials(stream)?;
+            if uid != self.uid {
+                return Err(anyhow!(
+                    "Process {} is run by another user (uid={})",
+                    pid,
+                    uid
+                ));
+            }
+            let pid = Pid::from_u32(pid);
+            let mut system = System::new_with_specifics(RefreshKind::new());
+            system.refresh_process(pid);
+            let exe = system
+                .process(pid)
+                .map(|process| canonical_path(process.exe()))
+                .ok_or_else(|| anyhow!("Failed to retrieve information of process {}", pid))?;
+            if exe != self.exe {
+                return Err(anyhow!(
+                    "Process {} is {} rather than {}",
+                    pid,
+                    exe.to_string_lossy(),
+                    self.exe.to_string_lossy()
+                ));
+            }
+            if let Some(ref callers) = self.callers {
+                let caller = CurrentCaller::parent_of(pid)?;
+                if !callers.iter().any(|c| caller.matches(c)) {
+                    return Err(anyhow!(
+                        "{} (uid={}, gid={}) is not allowed to call git-credential-keepassxc",
+                        caller.path.to_string_lossy(),
+                        caller.uid,
+                        caller.gid
+                    ));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct AgentState {
+        client_id: String,
+        /// Decrypted test-associate responses by database ID and key, of associations which
+        /// have been tested successfully
+        associations: HashMap<(String, String), String>,
+    }
+
+    impl AgentState {
+        fn forget_associations(&mut self, reason: &str) {
+            if !self.associations.is_empty() {
+                info!("Forgetting verified associations as {}", reason);
+                self.associations.clear();
+            }
+        }
+
+        fn observe_notifications(&mut self) -> Result<()> {
+            for notification in read_pending_messages()? {
+                debug!("Notification: {}", notification);
+                if let Ok(wrapper) = serde_json::from_str::<GenericResponseWrapper>(&notification) {
+                    if wrapper.action == KeePassAction::DatabaseLocked {
+                        self.forget_associations("database has been locked");
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Sends the request encrypted with the session of the agent. Errors are only returned when
+        /// the connection to KeePassXC is broken
+        fn handle(&mut self, job: &Job) -> Result<GenericResponseWrapper> {
+            debug!("Request: {}", job.action);
+            self.observe_notifications()?;
+            let association = if job.action == KeePassAction::TestAssociate {
+                serde_json::from_value::<TestAssociateRequest>(job.message.clone())
+                    .ok()
+                    .map(|request| (request.id, request.key))
+            } else {
+                None
+            };
+            if let Some(message) = association.as_ref().and_then(|a| self.associations.get(a)) {
+                return Ok(GenericResponseWrapper {
+                    action: job.action.clone(),
+                    message: Some(message.clone()),
+                    nonce: None,
+                    error: None,
+                    error_code: None,
+                    request_id: None,
+                });
+            }
+
+            let (nonce, nonce_b64) = nacl_nonce();
+            let mut message = job.message.clone();
+            // e.g. generate-password repeats these in the message
+            if let Some(fields) = message.as_object_mut() {
+                for (key, value) in [("nonce", &nonce_b64), ("clientID", &self.client_id)] {
+                    if fields.contains_key(key) {
+                        fields.insert(key.to_owned(), Value::from(value.as_str()));
+                    }
+                }
+            }
+            let request = GenericRequestWrapper {
+                action: job.action.clone(),
+                message: to_encrypted_json(&message, &nonce)?,
+                nonce: nonce_b64,
+                client_id: self.client_id.clone(),
+                request_id: new_request_id(),
+                trigger_unlock: job.trigger_unlock.clone(),
+            };
+            MessagingUtils::send_message(serde_json::to_string(&request)?)?;
+            let mut response = loop {
+                let response_json = MessagingUtils::receive_message()?;
+                // after generate-password request, we get an empty JSON as ACK
+                if response_json == "{}" {
+                    continue;
+                }
+                match serde_json::from_str::<GenericResponseWrapper>(&response_json) {
+                    // late responses to earlier requests are told apart by the request ID where
+                    // KeePassXC sends it back
+                    Ok(response)
+                        if response.action == request.action
+                            && response
+                                .request_id
+                                .as_ref()
+                                .is_none_or(|request_id| *request_id == request.request_id) =>
+                    {
+                        break response
+                    }
+                    Ok(response) if response.action == KeePassAction::DatabaseLocked => {
+                        self.forget_associations("database has been locked");
+                    }
+                    _ => {
+                        debug!("Discarding {}", response_json);
+                    }
+                }
+            };
+            response.message = match (response.message.take(), response.nonce.take()) {
+                (Some(message), Some(nonce)) => match to_decrypted_json(&message, &nonce) {
+                    Ok(message) => Some(message),
+                    Err(e) => {
+                        return Ok(error_response(
+                            &job.action,
+                            &format!("Agent failed to decrypt response, {}", e),
+                            KeePassErrorCode::CannotDecryptMessage,
+                        ));
+                    }
+                },
+                _ => None,
+            };
+
+            if let (Some((id, key)), Some(message)) = (association, &response.message) {
+                let success = serde_json::from_str::<TestAssociateResponse>(message)
+                    .ok()
+                    .and_then(|response| response.success)
+                    .is_some_and(|s| s.0);
+                if success {
+                    info!("Association with database {} verified", id);
+                    self.associations.insert((id, key), message.clone());
+                }
+            }
+            if job.action == KeePassAction::LockDatabase {
+                self.forget_associations("database has been locked");
+            }
+            if response.error_code == Some(KeePassErrorCode::DatabaseNotOpened) {
+                self.forget_associations("database is not opened");
+            }
+            Ok(response)
+        }
+    }
+
+    fn error_response(
+        action: &KeePassAction,
+        error: &str,
+        error_code: KeePassErrorCode,
+    ) -> GenericResponseWrapper {
+        GenericResponseWrapper {
+            action: action.clone(),
+            message: None,
+            nonce: None,
+            error: Some(error.to_owned()),
+            error_code: Some(error_code),
+            request_id: None,
+        }
+    }
+
+    /// Connection of a helper, which has a session of its own with the agent
+    struct HelperSession {
+        jobs: mpsc::Sender<Job>,
+        /// KeePassXC version to tell the helper
+        version: Option<String>,
+        keys_exchanged: bool,
+    }
+
+    impl HelperSession {
+        /// Errors are only returned when the agent no longer serves requests
+        fn handle(&mut self, message: &str) -> Result<String> {
+            let request: Envelope = match serde_json::from_str(message) {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("Failed to parse request: {}", e);
+                    let response = error_response(
+                        &KeePassAction::Unknown(String::new()),
+                        "Invalid request",
+                        KeePassErrorCode::Unknown,
+                    );
+                    return Ok(serde_json::to_string(&response)?);
+                }
+            };
+            debug!("Request from helper: {}", request.action);
+            let response = if request.action == KeePassAction::ChangePublicKeys {
+                self.change_public_keys(&request)
+            } else {
+                self.relay(&request)?
+            };
+            match response {
+                Ok(response) => Ok(response),
+                Err(e) => {
+                    warn!("Rejecting {} request: {}", request.action, e);
+                    let mut response = error_response(
+                        &request.action,
+                        &e.to_string(),
+                        if request.action == KeePassAction::ChangePublicKeys {
+                            KeePassErrorCode::KeyChangeFailed
+                        } else {
+                            KeePassErrorCode::CannotDecryptMessage
+                        },
+                    );
+                    response.request_id = request.request_id;
+                    Ok(serde_json::to_string(&response)?)
+                }
+            }
+        }
+
+        /// Answers the key exchange with a key pair of this connection, rather than passing it
+        /// on which would invalidate the session of the agent
+        fn change_public_keys(&mut self, request: &Envelope) -> Result<String> {
+            if self.keys_exchanged {
+                return Err(anyhow!("Keys have been exchanged already"));
+            }
+            let helper_pubkey = to_public_key(
+                request
+                    .public_key
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Missing public key"))?,
+            )?;
+            let session_seckey = generate_secret_key();
+            // each connection is served by a thread of its own, hence its own crypto_box
+            get_client_box(Some(&helper_pubkey), Some(&session_seckey))?;
+            self.keys_exchanged = true;
+            let response = ChangePublicKeysResponse {
+                action: Some(KeePassAction::ChangePublicKeys),
+                public_key: Some(base64::encode(session_seckey.public_key().as_bytes())),
+                version: self.version.clone(),
+                success: Some(KeePassBoolean(true)),
+            };
+            Ok(serde_json::to_string(&response)?)
+        }
+
+        /// Decrypts the request, has it sent to KeePassXC and encrypts the response. The inner
+        /// result is the response or why the request has been rejected
+        fn relay(&self, request: &Envelope) -> Result<Result<String>> {
+            if !self.keys_exchanged {
+                return Ok(Err(anyhow!("Keys have not been exchanged with agent")));
+            }
+            let message = match (&request.message, &request.nonce) {
+                (Some(message), Some(nonce)) => to_decrypted_json(message, nonce)
+                    .and_then(|json| Ok(serde_json::from_str::<Value>(&json)?)),
+                _ => Err(anyhow!("Missing message or nonce")),
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => return Ok(Err(e)),
+            };
+            let (reply_sender, reply_receiver) = mpsc::channel();
+            self.jobs
+                .send(Job {
+                    action: request.action.clone(),
+                    message,
+                    trigger_unlock: request.trigger_unlock.clone(),
+                    reply: reply_sender,
+                })
+                .map_err(|_| anyhow!("Agent has stopped"))?;
+            let mut response = reply_receiver.recv()?;
+            if let Some(message) = response.message.take() {
+                let (nonce, nonce_b64) = nacl_nonce();
+                let message: Value = serde_json::from_str(&message)?;
+                response.message = Some(to_encrypted_json(&message, &nonce)?);
+                response.nonce = Some(nonce_b64);
+            }
+            response.request_id = request.request_id.clone();
+            Ok(Ok(serde_json::to_string(&response)?))
+        }
+    }
+
+    /// Reads the next JSON from a helper, or None if it has disconnected
+    fn read_message(
+        stream: &mut UnixStream,
+        decoder: &mut JsonStreamDecoder,
+    ) -> Result<Option<String>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(message) = decoder.next_message() {
+                return Ok(Some(message));
+            }
+            match stream.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(len) => decoder.feed(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn serve_helper(mut stream: UnixStream, mut session: HelperSession) -> Result<()> {
+        let mut decoder = JsonStreamDecoder::new();
+        while let Some(message) = read_message(&mut stream, &mut decoder)? {
+            let response = session.handle(&message)?;
+            stream.write_all(response.as_bytes())?;
+        }
+        debug!("Helper disconnected");
+        Ok(())
+    }
+
+    fn bind<T: AsRef<Path>>(path: T) -> Result<UnixListener> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(SOCKET_DIR_MODE)
+                .create(dir)?;
+        }
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!(
+                    "Agent is already running at {}",
+                    path.to_string_lossy()
+                ));
+            }
+            warn!("Removing stale socket {}", path.to_string_lossy());
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(SOCKET_MODE))?;
+        Ok(listener)
+    }
+
+    pub fn agent<T: AsRef<Path>>(config_path: T, args: &cli::SubAgentArgs) -> Result<()> {
+        // callers are read once, so that they don't have to be decrypted for each connection
+        let config = Config::read_from(config_path.as_ref())?;
+        let callers = if requires_caller_verification(&config) {
+            Some(config.get_callers()?)
+        } else {
+            None
+        };
+        let peers = Arc::new(AllowedPeers {
+            // SAFETY: geteuid is always successful
+            uid: unsafe { libc::geteuid() },
+            exe: canonical_path(&std::env::current_exe()?),
+            callers,
+        });
+
+        set_use_agent(false);
+        let (client_id, _, _) = start_session()?;
+        let version = server_version().map(|v| v.to_string());
+        let mut state = AgentState {
+            client_id,
+            associations: HashMap::new(),
+        };
+
+        let socket_path = socket::get_agent_socket_path()?;
+        let listener = bind(&socket_path)?;
+        let _guard = SocketGuard(socket_path.clone());
+        info!("Agent listening on {}", socket_path.to_string_lossy());
+
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let peers = peers.clone();
+                        let session = HelperSession {
+                            jobs: job_sender.clone(),
+                            version: version.clone(),
+                            keys_exchanged: false,
+                        };
+                        thread::spawn(move || {
+                            if let Err(e) = peers.verify(&stream) {
+                                warn!("Refusing connection: {}", e);
+                                return;
+                            }
+                            debug!("Helper connected");
+                            if let Err(e) = serve_helper(stream, session) {
+                                warn!("Failed to serve helper: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                    }
+                }
+            }
+        });
+
+        // all messages to KeePassXC are sent from this thread, where the session lives
+        let idle_timeout = Duration::from_secs(args.idle_timeout);
+        loop {
+            let job = if args.idle_timeout > 0 {
+                job_receiver.recv_timeout(idle_timeout)
+            } else {
+                job_receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            let job = match job {
+                Ok(job) => job,
+                Err(RecvTimeoutError::Timeout) => {
+                    info!("No requests in {}s, exiting", args.idle_timeout);
+                    return Ok(());
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("Agent stopped accepting connections"));
+                }
+            };
+            match state.handle(&job) {
+                Ok(response) => {
+                    let _ = job.reply.send(response);
+                }
+                Err(e) => {
+                    // helpers wait for the response of the same action
+                    let _ = job.reply.send(error_response(
+                        &job.action,
+                        &format!("Agent lost connection to KeePassXC, {}", e),
+                        KeePassErrorCode::TimeoutOrNotConnected,
+                    ));
+                    return Err(e.context("Lost connection to KeePassXC"));
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_00_read_message() {
+            let (mut client, mut server) = UnixStream::pair().unwrap();
+            client
+                .write_all(
+                    br#"{"action":"change-public-keys"} {"action":"get-logins","message":"{}"}"#,
+                )
+                .unwrap();
+            let mut decoder = JsonStreamDecoder::new();
+            assert_eq!(
+                read_message(&mut server, &mut decoder).unwrap().unwrap(),
+                r#"{"action":"change-public-keys"}"#
+            );
+            assert_eq!(
+                read_message(&mut server, &mut decoder).unwrap().unwrap(),
+                r#"{"action":"get-logins","message":"{}"}"#
+            );
+            drop(client);
+            assert!(read_message(&mut server, &mut decoder).unwrap().is_none());
+        }
+
+        #[test]
+        fn test_01_peer_credentials() {
+            let (client, server) = UnixStream::pair().unwrap();
+            let (pid, uid) = peer_credentials(&server).unwrap();
+            assert_eq!(pid, std::process::id());
+            assert_eq!(uid, unsafe { libc::geteuid() });
+
+            let peers = AllowedPeers {
+                uid,
+                exe: canonical_path(&std::env::current_exe().unwrap()),
+                callers: None,
+            };
+            assert!(peers.verify(&server).is_ok());
+            let peers = AllowedPeers {
+                uid: uid + 1,
+                ..peers
+            };
+            assert!(peers.verify(&server).is_err());
+            let peers = AllowedPeers {
+                uid,
+                exe: PathBuf::from("/nonexistent"),
+                callers: None,
+            };
+            assert!(peers.verify(&client).is_err());
+        }
+
+        #[test]
+        fn test_02_helper_session() {
+            // crypto_box of the connection is per thread
+            thread::spawn(|| {
+                let (jobs, _) = mpsc::channel();
+                let mut session = HelperSession {
+                    jobs,
+                    version: Some("2.7.6".to_owned()),
+                    keys_exchanged: false,
+                };
+                let response: Value = serde_json::from_str(
+                    &session
+                        .handle(r#"{"action":"test-associate","message":"","nonce":""}"#)
+                        .unwrap(),
+                )
+                .unwrap();
+                assert!(response.get("message").unwrap().is_null());
+                assert_eq!(response["errorCode"], "4");
+
+                let helper_pubkey = generate_secret_key().public_key();
+                let request = ChangePublicKeysRequest::new("helper", &helper_pubkey);
+                let request = serde_json::to_string(&request).unwrap();
+                let response: ChangePublicKeysResponse =
+                    serde_json::from_str(&session.handle(&request).unwrap()).unwrap();
+                assert!(response.success.as_ref().is_some_and(|s| s.0));
+                assert_eq!(response.version.as_deref(), Some("2.7.6"));
+                assert!(response.get_public_key().is_some());
+
+                // the session of a connection can't be replaced
+                let response: Value =
+                    serde_json::from_str(&session.handle(&request).unwrap()).unwrap();
+                assert_eq!(response["errorCode"], "9");
+            })
+            .join()
+            .unwrap();
+        }
+    }
+}
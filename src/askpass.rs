@@ -0,0 +1,195 @@
+//! GIT_ASKPASS, SSH_ASKPASS and SUDO_ASKPASS, which receive the prompt as the only argument and
+//! expect the answer in standard output
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, start_session, verify_caller};
+use anyhow::{anyhow, Result};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum AskpassField {
+    Username,
+    Password,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct AskpassRequest {
+    url: String,
+    username: Option<String>,
+    field: AskpassField,
+}
+
+/// Text between the first pair of single quotes
+fn quoted(s: &str) -> Option<&str> {
+    let start = s.find('\'')? + 1;
+    let end = start + s[start..].find('\'')?;
+    Some(&s[start..end])
+}
+
+/// Moves user info out of the URL, e.g. https://foo@example.com becomes https://example.com and foo
+fn split_userinfo(url: &str) -> (String, Option<String>) {
+    if let Some((scheme, rest)) = url.split_once("://") {
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+        if let Some((userinfo, host)) = rest[..authority_end].rsplit_once('@') {
+            let username = userinfo.split(':').next().unwrap_or(userinfo);
+            return (
+                format!("{}://{}{}", scheme, host, &rest[authority_end..]),
+                Some(username.to_owned()),
+            );
+        }
+    }
+    (url.to_owned(), None)
+}
+
+fn parse_prompt(prompt: &str) -> Option<AskpassRequest> {
+    let prompt = prompt.trim();
+    // Git: Username for 'https://example.com':
+    if prompt.starts_with("Username for '") {
+        let (url, _) = split_userinfo(quoted(prompt)?);
+        return Some(AskpassRequest {
+            url,
+            username: None,
+            field: AskpassField::Username,
+        });
+    }
+    // Git: Password for 'https://foo@example.com':
+    if prompt.starts_with("Password for '") {
+        let (url, username) = split_userinfo(quoted(prompt)?);
+        return Some(AskpassRequest {
+            url,
+            username,
+            field: AskpassField::Password,
+        });
+    }
+    // sudo: [sudo] password for foo:
+    if let Some(rest) = prompt.strip_prefix("[sudo] password for ") {
+        return Some(AskpassRequest {
+            url: "sudo://localhost".to_owned(),
+            username: Some(rest.trim_end_matches(':').trim().to_owned()),
+            field: AskpassField::Password,
+        });
+    }
+    // OpenSSH: Enter passphrase for key '/home/foo/.ssh/id_ed25519':
+    if prompt.starts_with("Enter passphrase for key '") {
+        let key_name = Path::new(quoted(prompt)?).file_name()?.to_str()?;
+        return Some(AskpassRequest {
+            url: format!("ssh-key://{}", key_name),
+            username: None,
+            field: AskpassField::Password,
+        });
+    }
+    // OpenSSH: (foo@example.com) Password:
+    if let Some(rest) = prompt.strip_prefix('(') {
+        let (user_host, rest) = rest.split_once(')')?;
+        if rest.trim().eq_ignore_ascii_case("password:") {
+            let (username, host) = user_host.rsplit_once('@')?;
+            return Some(AskpassRequest {
+                url: format!("ssh://{}", host),
+                username: Some(username.to_owned()),
+                field: AskpassField::Password,
+            });
+        }
+    }
+    // OpenSSH: foo@example.com's password:
+    if let Some(user_host) = prompt.strip_suffix("'s password:") {
+        let (username, host) = user_host.rsplit_once('@')?;
+        return Some(AskpassRequest {
+            url: format!("ssh://{}", host),
+            username: Some(username.to_owned()),
+            field: AskpassField::Password,
+        });
+    }
+    None
+}
+
+pub fn askpass<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubAskpassArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let request = parse_prompt(&args.prompt)
+        .ok_or_else(|| anyhow!("Failed to understand prompt {}", args.prompt))?;
+    debug!("Askpass request: {:?}", request);
+    let url = normalise_url(&request.url, config.get_url_policies());
+    let (client_id, _, _) = start_session()?;
+    let login_entry = get_first_login_for(
+        &config,
+        &client_id,
+        &url,
+        request.username.as_deref(),
+        &entry_filters,
+        unlock_options,
+    )?
+    .ok_or_else(|| anyhow!("No matching logins found for {}", url))?;
+
+    let secret = match request.field {
+        AskpassField::Username => &login_entry.login,
+        AskpassField::Password => &login_entry.password,
+    };
+    io::stdout().write_all(format!("{}\n", secret).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str, username: Option<&str>, field: AskpassField) -> Option<AskpassRequest> {
+        Some(AskpassRequest {
+            url: url.to_owned(),
+            username: username.map(str::to_owned),
+            field,
+        })
+    }
+
+    #[test]
+    fn test_00_git_prompts() {
+        assert_eq!(
+            parse_prompt("Username for 'https://example.com': "),
+            request("https://example.com", None, AskpassField::Username)
+        );
+        assert_eq!(
+            parse_prompt("Password for 'https://foo@example.com/bar.git': "),
+            request(
+                "https://example.com/bar.git",
+                Some("foo"),
+                AskpassField::Password
+            )
+        );
+    }
+
+    #[test]
+    fn test_01_ssh_and_sudo_prompts() {
+        assert_eq!(
+            parse_prompt("foo@example.com's password: "),
+            request("ssh://example.com", Some("foo"), AskpassField::Password)
+        );
+        assert_eq!(
+            parse_prompt("(foo@example.com) Password: "),
+            request("ssh://example.com", Some("foo"), AskpassField::Password)
+        );
+        assert_eq!(
+            parse_prompt("Enter passphrase for key '/home/foo/.ssh/id_ed25519': "),
+            request("ssh-key://id_ed25519", None, AskpassField::Password)
+        );
+        assert_eq!(
+            parse_prompt("[sudo] password for foo: "),
+            request("sudo://localhost", Some("foo"), AskpassField::Password)
+        );
+    }
+
+    #[test]
+    fn test_02_unknown_prompts() {
+        assert_eq!(parse_prompt("Are you sure? (yes/no) "), None);
+        assert_eq!(parse_prompt("Password for 'unterminated: "), None);
+    }
+}
@@ -0,0 +1,120 @@
+//! AWS credential_process, see
+//! https://docs.aws.amazon.com/cli/latest/userguide/cli-configure-sourcing-external.html
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::git::GitCredentialMessage;
+use crate::keepassxc::messages::LoginEntry;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, start_session, verify_caller};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::Path;
+
+const CREDENTIAL_PROCESS_VERSION: u8 = 1;
+// by default access key ID and secret access key are the username and password of the entry
+const AWS_ACCESS_KEY_ID_FIELD: &str = "KPH: aws_access_key_id";
+const AWS_SECRET_ACCESS_KEY_FIELD: &str = "KPH: aws_secret_access_key";
+const AWS_SESSION_TOKEN_FIELD: &str = "KPH: aws_session_token";
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredential {
+    version: u8,
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+impl From<LoginEntry> for AwsCredential {
+    fn from(login_entry: LoginEntry) -> Self {
+        let get_field = |key: &str| {
+            login_entry
+                .get_string_field(key)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+        let mut attributes = GitCredentialMessage::default();
+        if let Some(ref string_fields) = login_entry.string_fields {
+            attributes.set_credential_attributes(string_fields);
+        }
+        Self {
+            version: CREDENTIAL_PROCESS_VERSION,
+            access_key_id: get_field(AWS_ACCESS_KEY_ID_FIELD)
+                .unwrap_or_else(|| login_entry.login.clone()),
+            secret_access_key: get_field(AWS_SECRET_ACCESS_KEY_FIELD)
+                .unwrap_or_else(|| login_entry.password.clone()),
+            session_token: get_field(AWS_SESSION_TOKEN_FIELD),
+            expiration: attributes.password_expiry_rfc3339(),
+        }
+    }
+}
+
+pub fn aws<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubAwsArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let url = normalise_url(&args.url, config.get_url_policies());
+    let (client_id, _, _) = start_session()?;
+    let login_entry = get_first_login_for(
+        &config,
+        &client_id,
+        &url,
+        None,
+        &entry_filters,
+        unlock_options,
+    )?
+    .ok_or_else(|| anyhow!("No matching logins found for {}", url))?;
+
+    let credential = AwsCredential::from(login_entry);
+    io::stdout().write_all(serde_json::to_string(&credential)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_00_credential_from_login_entry() {
+        let login_entry: LoginEntry = serde_json::from_str(
+            r#"{"login":"AKIAEXAMPLE","name":"AWS","password":"secret","uuid":"0"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&AwsCredential::from(login_entry)).unwrap(),
+            r#"{"Version":1,"AccessKeyId":"AKIAEXAMPLE","SecretAccessKey":"secret"}"#
+        );
+
+        let mut login_entry: LoginEntry =
+            serde_json::from_str(r#"{"login":"me","name":"AWS","password":"password","uuid":"0"}"#)
+                .unwrap();
+        login_entry.string_fields = Some(
+            [
+                (AWS_ACCESS_KEY_ID_FIELD, "ASIAEXAMPLE"),
+                (AWS_SECRET_ACCESS_KEY_FIELD, "secret"),
+                (AWS_SESSION_TOKEN_FIELD, "token"),
+                ("KPH: password_expiry_utc", "1700000000"),
+            ]
+            .iter()
+            .map(|(key, value)| HashMap::from([(key.to_string(), value.to_string())]))
+            .collect(),
+        );
+        assert_eq!(
+            serde_json::to_string(&AwsCredential::from(login_entry)).unwrap(),
+            r#"{"Version":1,"AccessKeyId":"ASIAEXAMPLE","SecretAccessKey":"secret","SessionToken":"token","Expiration":"2023-11-14T22:13:20Z"}"#
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Cargo credential provider protocol, see
+//! https://doc.rust-lang.org/cargo/reference/credential-provider-protocol.html
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::git::GitCredentialMessage;
+use crate::keepassxc::errors::KeePassError;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{erase_login_for, get_first_login_for, start_session, store_login_for, verify_caller};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const PROTOCOL_VERSION: u32 = 1;
+// username of new entries when registry name is unknown, e.g. crates.io
+const DEFAULT_USERNAME: &str = "cargo";
+
+#[derive(Serialize)]
+struct CargoHello {
+    v: Vec<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CargoRequest {
+    v: u32,
+    registry: CargoRegistryInfo,
+    kind: String,
+    #[serde(default)]
+    token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct CargoRegistryInfo {
+    index_url: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+enum CargoResponse {
+    Ok(CargoResponseOk),
+    Err(CargoError),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum CargoResponseOk {
+    Get {
+        token: String,
+        cache: &'static str,
+        operation_independent: bool,
+    },
+    Login,
+    Logout,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum CargoError {
+    NotFound,
+    OperationNotSupported,
+    Other {
+        message: String,
+        #[serde(rename = "caused-by", skip_serializing_if = "Vec::is_empty")]
+        caused_by: Vec<String>,
+    },
+}
+
+impl From<anyhow::Error> for CargoError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Other {
+            message: e.to_string(),
+            caused_by: e.chain().skip(1).map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+/// Index URLs may come with a protocol prefix, e.g. sparse+https://example.com/index/
+fn index_url_to_url<T: AsRef<str>>(index_url: T) -> String {
+    let index_url = index_url.as_ref();
+    index_url
+        .strip_prefix("sparse+")
+        .or_else(|| index_url.strip_prefix("registry+"))
+        .unwrap_or(index_url)
+        .to_owned()
+}
+
+pub fn cargo<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubCargoArgs,
+) -> Result<()> {
+    if !args.cargo_plugin {
+        return Err(anyhow!(
+            "This subcommand is meant to be run by Cargo as a credential provider"
+        ));
+    }
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let mut stdout = io::stdout();
+    let hello = CargoHello {
+        v: vec![PROTOCOL_VERSION],
+    };
+    stdout.write_all((serde_json::to_string(&hello)? + "\n").as_bytes())?;
+    stdout.flush()?;
+
+    // Cargo may send more than one request before closing standard input
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<CargoRequest>(&line) {
+            Ok(request) => {
+                debug!(
+                    "Cargo {} request for {}",
+                    request.kind, request.registry.index_url
+                );
+                match handle_request(
+                    config_path.as_ref(),
+                    unlock_options,
+                    &entry_filters,
+                    args,
+                    request,
+                ) {
+                    Ok(response) => CargoResponse::Ok(response),
+                    Err(e) => {
+                        warn!("Failed to handle Cargo request: {:?}", e);
+                        CargoResponse::Err(e)
+                    }
+                }
+            }
+            Err(e) => CargoResponse::Err(CargoError::from(anyhow::Error::from(e))),
+        };
+        stdout.write_all((serde_json::to_string(&response)? + "\n").as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn handle_request(
+    config_path: &Path,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: &EntryFilters,
+    args: &cli::SubCargoArgs,
+    request: CargoRequest,
+) -> Result<CargoResponseOk, CargoError> {
+    if request.v != PROTOCOL_VERSION {
+        return Err(anyhow!("Unsupported protocol version {}", request.v).into());
+    }
+    // configuration file is re-read as login and logout may update it
+    let config = Config::read_from(config_path)?;
+    let url = index_url_to_url(&request.registry.index_url);
+
+    match request.kind.as_str() {
+        "get" => {
+            let url = normalise_url(&url, config.get_url_policies());
+            let (client_id, _, _) = start_session()?;
+            let login_entry = get_first_login_for(
+                &config,
+                &client_id,
+                &url,
+                None,
+                entry_filters,
+                unlock_options,
+            )?
+            .ok_or(CargoError::NotFound)?;
+            Ok(CargoResponseOk::Get {
+                token: login_entry.password,
+                cache: "session",
+                operation_independent: true,
+            })
+        }
+        "login" => {
+            let token = request.token.ok_or_else(|| {
+                anyhow!("Token is missing, please pass it to cargo login as an argument")
+            })?;
+            let git_req = GitCredentialMessage {
+                url: Some(url),
+                username: Some(
+                    request
+                        .registry
+                        .name
+                        .unwrap_or_else(|| DEFAULT_USERNAME.to_owned()),
+                ),
+                password: Some(token),
+                ..Default::default()
+            };
+            store_login_for(
+                config,
+                config_path,
+                unlock_options,
+                entry_filters,
+                args.create_in.as_ref(),
+                git_req,
+            )?;
+            Ok(CargoResponseOk::Login)
+        }
+        "logout" => {
+            // same as Git, logins are quarantined rather than deleted, only the one of the
+            // username cargo login stores
+            let git_req = GitCredentialMessage {
+                url: Some(url),
+                username: Some(
+                    request
+                        .registry
+                        .name
+                        .unwrap_or_else(|| DEFAULT_USERNAME.to_owned()),
+                ),
+                ..Default::default()
+            };
+            erase_login_for(
+                config,
+                config_path,
+                unlock_options,
+                entry_filters,
+                &cli::SubEraseArgs::default(),
+                git_req,
+            )
+            .map_err(|e| match e.downcast_ref::<KeePassError>() {
+                Some(keepass_error) if keepass_error.is_no_logins_found() => CargoError::NotFound,
+                _ => e.into(),
+            })?;
+            Ok(CargoResponseOk::Logout)
+        }
+        _ => Err(CargoError::OperationNotSupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_index_url_to_url() {
+        assert_eq!(
+            index_url_to_url("sparse+https://example.com/index/"),
+            "https://example.com/index/"
+        );
+        assert_eq!(
+            index_url_to_url("https://github.com/rust-lang/crates.io-index"),
+            "https://github.com/rust-lang/crates.io-index"
+        );
+    }
+
+    #[test]
+    fn test_01_request() {
+        let request: CargoRequest = serde_json::from_str(
+            r#"{"v":1,"registry":{"index-url":"sparse+https://example.com/index/","name":"example","headers":[]},"kind":"get","operation":"read","args":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(request.v, 1);
+        assert_eq!(request.kind, "get");
+        assert_eq!(
+            request.registry.index_url,
+            "sparse+https://example.com/index/"
+        );
+        assert_eq!(request.registry.name.as_deref(), Some("example"));
+        assert!(request.token.is_none());
+    }
+
+    #[test]
+    fn test_02_response() {
+        let response = CargoResponse::Ok(CargoResponseOk::Get {
+            token: "foo".to_owned(),
+            cache: "session",
+            operation_independent: true,
+        });
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"Ok":{"kind":"get","token":"foo","cache":"session","operation_independent":true}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&CargoResponse::Ok(CargoResponseOk::Logout)).unwrap(),
+            r#"{"Ok":{"kind":"logout"}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&CargoResponse::Err(CargoError::NotFound)).unwrap(),
+            r#"{"Err":{"kind":"not-found"}}"#
+        );
+        let error = CargoError::from(anyhow!("foo").context("bar"));
+        assert_eq!(
+            serde_json::to_string(&CargoResponse::Err(error)).unwrap(),
+            r#"{"Err":{"kind":"other","message":"bar","caused-by":["foo"]}}"#
+        );
+    }
+}
@@ -1,25 +1,56 @@
+use crate::git::format::OutputFormat;
 use anyhow::anyhow;
 use clap::{
-    builder::{NonEmptyStringValueParser, TypedValueParser, ValueParserFactory},
+    builder::{
+        BoolishValueParser, NonEmptyStringValueParser, TypedValueParser, ValueParserFactory,
+    },
     ArgAction, Args, Parser, Subcommand,
 };
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
 use std::{num, str::FromStr};
 
+// executable names of other tools' helpers, and the subcommands that speak their protocols
+const MULTI_CALL_NAMES: &[(&str, &str)] = &[
+    ("docker-credential-keepassxc", "docker"),
+    ("cargo-credential-keepassxc", "cargo"),
+    ("terraform-credentials-keepassxc", "terraform"),
+    ("askpass-keepassxc", "askpass"),
+    ("pinentry-keepassxc", "pinentry"),
+];
+
 /// Helper that allows Git and shell scripts to use KeePassXC as credential store
 #[derive(Parser)]
 #[clap(author, version, long_version = long_version(), about, long_about = None)]
 #[clap(propagate_version = true)]
 pub struct MainArgs {
     /// Specify configuration JSON file path
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "GIT_CREDENTIAL_KEEPASSXC_CONFIG")]
     pub config: Option<String>,
-    /// Specify KeePassXC socket path (environment variable: KEEPASSXC_BROWSER_SOCKET_PATH)
-    #[clap(short, long, value_parser)]
+    /// Specify KeePassXC socket path
+    #[clap(short, long, value_parser, env = "KEEPASSXC_BROWSER_SOCKET_PATH")]
     pub socket: Option<String>,
     /// Try unlocking database. Applies to get, totp, store, and groups only.
-    /// Takes one argument in the format of [<MAX_RETRIES>[,<INTERVAL_MS>]]. Use 0 to retry indefinitely. The default interval is 1000ms.
-    #[clap(long, value_parser, verbatim_doc_comment)]
+    /// Takes one argument in the format of [<MAX_RETRIES>[,<INTERVAL_MS>]]. Waits up to MAX_RETRIES * INTERVAL_MS for the database to be unlocked.
+    /// Use 0 to wait indefinitely. The default interval is 1000ms, which is also how often KeePassXC is polled in case it doesn't notify of the unlock.
+    #[clap(
+        long,
+        value_parser,
+        verbatim_doc_comment,
+        env = "GIT_CREDENTIAL_KEEPASSXC_UNLOCK"
+    )]
     pub unlock: Option<UnlockOptions>,
+    /// Time in milliseconds to wait for each response from KeePassXC, including access confirmation dialogs.
+    /// Use 0 to wait indefinitely.
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = 0,
+        verbatim_doc_comment,
+        env = "GIT_CREDENTIAL_KEEPASSXC_RESPONSE_TIMEOUT"
+    )]
+    pub response_timeout: u64,
     /// Group(s) to get credentials from
     #[clap(long, value_parser)]
     pub group: Vec<String>,
@@ -29,6 +60,27 @@ pub struct MainArgs {
     /// Do not filter out entries with advanced field 'KPH: git' set to false
     #[clap(long, value_parser)]
     pub no_filter: bool,
+    /// Also look up path prefixes of the URL down to the bare host, with entries found for longer ones first.
+    /// Helpful when credential.useHttpPath is enabled.
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    pub path_prefixes: bool,
+    /// Look up entries as for an HTTP Basic Auth dialog, so that KeePassXC honours the entries' HTTP Basic Auth settings.
+    /// Can also be set per host via url_policies in configuration file.
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    pub http_auth: bool,
+    /// Submit (form action) URL sent along with the URL, which KeePassXC uses to rank entries
+    #[clap(long, value_parser)]
+    pub submit_url: Option<String>,
+    /// Delete entries rejected by Git instead of quarantining them, same as 'erase --delete'.
+    /// Git passes helper options before the action, so this can be set in credential.helper.
+    #[clap(
+        long,
+        action(ArgAction::SetTrue),
+        value_parser = BoolishValueParser::new(),
+        verbatim_doc_comment,
+        env = "GIT_CREDENTIAL_KEEPASSXC_DELETE_REJECTED"
+    )]
+    pub delete_rejected: bool,
     /// Sets the level of verbosity (-v: WARNING; -vv: INFO; -vvv: DEBUG in debug builds)
     #[clap(short, action(ArgAction::Count))]
     pub verbose: u8,
@@ -42,10 +94,29 @@ impl HasEntryFilters for MainArgs {
             kph: !self.no_filter,
             groups: self.group.clone(),
             git_groups: self.git_groups,
+            path_prefixes: self.path_prefixes,
+            http_auth: self.http_auth,
+            submit_url: self.submit_url.clone(),
         }
     }
 }
 
+/// Inserts the subcommand implied by the executable name, e.g. docker-credential-keepassxc get
+/// becomes git-credential-keepassxc docker get. Global options are then only available through
+/// environment variables.
+pub fn multi_call_args<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut args: Vec<_> = args.into_iter().collect();
+    let name = args
+        .first()
+        .and_then(|arg0| Path::new(arg0).file_stem())
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    if let Some((_, subcommand)) = MULTI_CALL_NAMES.iter().find(|(n, _)| *n == name) {
+        args.insert(1, OsString::from(subcommand));
+    }
+    args
+}
+
 fn long_version() -> &'static str {
     let version = env!("CARGO_PKG_VERSION");
     let mut features = vec![];
@@ -79,6 +150,18 @@ pub enum Subcommands {
     Lock(SubLockArgs),
     Groups(SubGroupsArgs),
     GeneratePassword(SubGeneratePasswordArgs),
+    Quarantine(SubQuarantineArgs),
+    Docker(SubDockerArgs),
+    Cargo(SubCargoArgs),
+    Kubernetes(SubKubernetesArgs),
+    Aws(SubAwsArgs),
+    Terraform(SubTerraformArgs),
+    Askpass(SubAskpassArgs),
+    Pinentry(SubPinentryArgs),
+    Exec(SubExecArgs),
+    Inject(SubInjectArgs),
+    Agent(SubAgentArgs),
+    Watch(SubWatchArgs),
     Configure(SubConfigureArgs),
     Caller(SubCallerArgs),
     Edit(SubEditArgs),
@@ -96,6 +179,18 @@ impl Subcommands {
             Self::Lock(_) => "lock",
             Self::Groups(_) => "groups",
             Self::GeneratePassword(_) => "generate-password",
+            Self::Quarantine(_) => "quarantine",
+            Self::Docker(_) => "docker",
+            Self::Cargo(_) => "cargo",
+            Self::Kubernetes(_) => "kubernetes",
+            Self::Aws(_) => "aws",
+            Self::Terraform(_) => "terraform",
+            Self::Askpass(_) => "askpass",
+            Self::Pinentry(_) => "pinentry",
+            Self::Exec(_) => "exec",
+            Self::Inject(_) => "inject",
+            Self::Agent(_) => "agent",
+            Self::Watch(_) => "watch",
             Self::Configure(_) => "configure",
             Self::Caller(_) => "caller",
             Self::Edit(_) => "edit",
@@ -108,10 +203,53 @@ impl Subcommands {
 pub trait GetOperation {
     fn get_mode(&self) -> GetMode;
     fn advanced_fields(&self) -> bool;
-    fn json(&self) -> bool;
+    fn output_format(&self) -> OutputFormat;
     fn raw(&self) -> bool;
 }
 
+fn output_format(
+    json: bool,
+    format: &Option<OutputFormat>,
+    field: &Option<String>,
+) -> OutputFormat {
+    if json {
+        OutputFormat::Json
+    } else if let Some(field) = field {
+        OutputFormat::Field(field.clone())
+    } else {
+        format.clone().unwrap_or_default()
+    }
+}
+
+/// Entry filters of subcommands, added to the global ones
+#[derive(Args)]
+pub struct EntryFilterArgs {
+    /// Group(s) to get credentials from
+    #[clap(long, value_parser)]
+    pub group: Vec<String>,
+    /// Get credentials from the dedicated group created by 'configure' subcommand
+    #[clap(long, value_parser)]
+    pub git_groups: bool,
+    /// Do not filter out entries with advanced field 'KPH: git' set to false
+    #[clap(long, value_parser)]
+    pub no_filter: bool,
+}
+
+impl HasEntryFilters for EntryFilterArgs {
+    fn entry_filters(&self) -> EntryFilters {
+        EntryFilters {
+            kph: !self.no_filter,
+            groups: self.group.clone(),
+            git_groups: self.git_groups,
+            path_prefixes: false,
+            http_auth: false,
+            submit_url: None,
+        }
+    }
+}
+
+impl HasLocalEntryFilters for EntryFilterArgs {}
+
 /// Get credential (used by Git)
 #[derive(Args)]
 pub struct SubGetArgs {
@@ -133,6 +271,12 @@ pub struct SubGetArgs {
     /// Print JSON
     #[clap(long, value_parser, conflicts_with = "raw")]
     pub json: bool,
+    /// Output format: git, json, export, dotenv, netrc, or a template such as '{username}:{password}'
+    #[clap(long, value_parser, conflicts_with_all = &["raw", "json", "field"])]
+    pub format: Option<OutputFormat>,
+    /// Print only the value of a field, e.g. password
+    #[clap(long, value_parser, conflicts_with_all = &["raw", "json"])]
+    pub field: Option<String>,
     /// Show raw output from KeePassXC
     #[clap(long, value_parser)]
     pub raw: bool,
@@ -144,6 +288,9 @@ impl HasEntryFilters for SubGetArgs {
             kph: !self.no_filter,
             groups: self.group.clone(),
             git_groups: self.git_groups,
+            path_prefixes: false,
+            http_auth: false,
+            submit_url: None,
         }
     }
 }
@@ -163,8 +310,8 @@ impl GetOperation for SubGetArgs {
         self.advanced_fields
     }
 
-    fn json(&self) -> bool {
-        self.json
+    fn output_format(&self) -> OutputFormat {
+        output_format(self.json, &self.format, &self.field)
     }
 
     fn raw(&self) -> bool {
@@ -175,18 +322,17 @@ impl GetOperation for SubGetArgs {
 /// Get TOTP
 #[derive(Args)]
 pub struct SubTotpArgs {
-    /// Group(s) to get credentials from
-    #[clap(long, value_parser)]
-    pub group: Vec<String>,
-    /// Get credentials from the dedicated group created by 'configure' subcommand
-    #[clap(long, value_parser)]
-    pub git_groups: bool,
-    /// Do not filter out entries with advanced field 'KPH: git' set to false
-    #[clap(long, value_parser)]
-    pub no_filter: bool,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
     /// Print JSON
     #[clap(long, value_parser, conflicts_with = "raw")]
     pub json: bool,
+    /// Output format: git, json, export, dotenv, netrc, or a template such as '{username}:{password}'
+    #[clap(long, value_parser, conflicts_with_all = &["raw", "json", "field"])]
+    pub format: Option<OutputFormat>,
+    /// Print only the value of a field, e.g. password
+    #[clap(long, value_parser, conflicts_with_all = &["raw", "json"])]
+    pub field: Option<String>,
     /// Show raw output from KeePassXC with entry UUIDs
     #[clap(long, value_parser)]
     pub raw: bool,
@@ -201,8 +347,8 @@ impl GetOperation for SubTotpArgs {
         false
     }
 
-    fn json(&self) -> bool {
-        self.json
+    fn output_format(&self) -> OutputFormat {
+        output_format(self.json, &self.format, &self.field)
     }
 
     fn raw(&self) -> bool {
@@ -210,51 +356,27 @@ impl GetOperation for SubTotpArgs {
     }
 }
 
-impl HasEntryFilters for SubTotpArgs {
-    fn entry_filters(&self) -> EntryFilters {
-        EntryFilters {
-            kph: !self.no_filter,
-            groups: self.group.clone(),
-            git_groups: self.git_groups,
-        }
-    }
-}
-
-impl HasLocalEntryFilters for SubTotpArgs {}
-
 /// Store credential (used by Git)
 #[derive(Args)]
 pub struct SubStoreArgs {
     /// Create new entries in specified group instead of the one created by 'configure' subcommand
     #[clap(long, value_parser)]
     pub create_in: Option<String>,
-    /// Group(s) to get credentials from
-    #[clap(long, value_parser)]
-    pub group: Vec<String>,
-    /// Get credentials from the dedicated group created by 'configure' subcommand
-    #[clap(long, value_parser)]
-    pub git_groups: bool,
-    /// Do not filter out entries with advanced field 'KPH: git' set to false
-    #[clap(long, value_parser)]
-    pub no_filter: bool,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
 }
 
-impl HasEntryFilters for SubStoreArgs {
-    fn entry_filters(&self) -> EntryFilters {
-        EntryFilters {
-            kph: !self.no_filter,
-            groups: self.group.clone(),
-            git_groups: self.git_groups,
-        }
-    }
+/// Quarantine rejected credential so that it won't be returned again (used by Git)
+#[derive(Args, Default)]
+pub struct SubEraseArgs {
+    /// Delete the rejected entries instead if KeePassXC supports it (may move them to the recycle bin depending on database settings)
+    #[clap(long, value_parser)]
+    pub delete: bool,
+    /// Print UUIDs of the entries that would be deleted or quarantined without changing anything
+    #[clap(long, value_parser)]
+    pub dry_run: bool,
 }
 
-impl HasLocalEntryFilters for SubStoreArgs {}
-
-/// [Not implemented] Erase credential (used by Git)
-#[derive(Args)]
-pub struct SubEraseArgs {}
-
 /// Lock KeePassXC database
 #[derive(Args)]
 pub struct SubLockArgs {}
@@ -273,6 +395,241 @@ pub struct SubGeneratePasswordArgs {
     /// Print JSON
     #[clap(long, value_parser)]
     pub json: bool,
+    /// Output format: git, json, export, dotenv, netrc, or a template such as '{password}'
+    #[clap(long, value_parser, conflicts_with_all = &["json", "field"])]
+    pub format: Option<OutputFormat>,
+    /// Print only the value of a field, i.e. password
+    #[clap(long, value_parser, conflicts_with = "json")]
+    pub field: Option<String>,
+}
+
+impl SubGeneratePasswordArgs {
+    pub fn output_format(&self) -> OutputFormat {
+        output_format(self.json, &self.format, &self.field)
+    }
+}
+
+/// List or clear credentials quarantined by 'erase' subcommand
+#[derive(Args)]
+pub struct SubQuarantineArgs {
+    #[clap(subcommand)]
+    pub command: QuarantineSubcommands,
+}
+
+#[derive(Subcommand)]
+pub enum QuarantineSubcommands {
+    List(SubQuarantineListArgs),
+    Clear(SubQuarantineClearArgs),
+}
+
+/// List quarantined credentials
+#[derive(Args)]
+pub struct SubQuarantineListArgs {
+    /// Print JSON
+    #[clap(long, value_parser)]
+    pub json: bool,
+}
+
+/// Clear quarantined credentials
+#[derive(Args)]
+pub struct SubQuarantineClearArgs {
+    /// Only clear quarantined credentials for this URL
+    #[clap(long, value_parser)]
+    pub url: Option<String>,
+    /// Only clear quarantined credentials of the entry with this UUID
+    #[clap(long, value_parser)]
+    pub uuid: Option<String>,
+}
+
+/// Act as a Docker credential helper (docker-credential-helpers protocol)
+#[derive(Args)]
+pub struct SubDockerArgs {
+    /// Create new entries in specified group instead of the one created by 'configure' subcommand
+    #[clap(long, value_parser)]
+    pub create_in: Option<String>,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    #[clap(subcommand)]
+    pub command: DockerSubcommands,
+}
+
+#[derive(Subcommand)]
+pub enum DockerSubcommands {
+    Get(SubDockerGetArgs),
+    Store(SubDockerStoreArgs),
+    Erase(SubDockerEraseArgs),
+    List(SubDockerListArgs),
+}
+
+/// Get credential of the server URL from standard input
+#[derive(Args)]
+pub struct SubDockerGetArgs {}
+
+/// Store credential in JSON from standard input
+#[derive(Args)]
+pub struct SubDockerStoreArgs {}
+
+/// Quarantine credential of the server URL from standard input
+#[derive(Args)]
+pub struct SubDockerEraseArgs {}
+
+/// List server URLs and usernames stored by Docker
+#[derive(Args)]
+pub struct SubDockerListArgs {}
+
+/// Act as a Cargo credential provider (registry tokens)
+#[derive(Args)]
+pub struct SubCargoArgs {
+    /// Create new entries in specified group instead of the one created by 'configure' subcommand
+    #[clap(long, value_parser)]
+    pub create_in: Option<String>,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// Speak the credential provider protocol (passed by Cargo)
+    #[clap(long, value_parser)]
+    pub cargo_plugin: bool,
+}
+
+/// Act as a Kubernetes exec credential plugin (cluster tokens)
+#[derive(Args)]
+pub struct SubKubernetesArgs {
+    /// Cluster server URL, by default read from KUBERNETES_EXEC_INFO (requires provideClusterInfo)
+    #[clap(long, value_parser)]
+    pub server: Option<String>,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+}
+
+/// Act as an AWS credential_process (access keys)
+#[derive(Args)]
+pub struct SubAwsArgs {
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// URL of the entry, e.g. aws://my-profile
+    #[clap(value_parser)]
+    pub url: String,
+}
+
+/// Act as a Terraform credentials helper (API tokens)
+#[derive(Args)]
+pub struct SubTerraformArgs {
+    /// Create new entries in specified group instead of the one created by 'configure' subcommand
+    #[clap(long, value_parser)]
+    pub create_in: Option<String>,
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    #[clap(subcommand)]
+    pub command: TerraformSubcommands,
+}
+
+#[derive(Subcommand)]
+pub enum TerraformSubcommands {
+    Get(SubTerraformHostArgs),
+    Store(SubTerraformHostArgs),
+    Forget(SubTerraformHostArgs),
+}
+
+#[derive(Args)]
+pub struct SubTerraformHostArgs {
+    /// Host name of the service, e.g. app.terraform.io
+    #[clap(value_parser)]
+    pub hostname: String,
+}
+
+/// Answer the prompt of GIT_ASKPASS, SSH_ASKPASS or SUDO_ASKPASS
+#[derive(Args)]
+pub struct SubAskpassArgs {
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// Prompt, e.g. "Password for 'https://foo@example.com': "
+    #[clap(value_parser, allow_hyphen_values = true)]
+    pub prompt: String,
+}
+
+/// Act as GnuPG pinentry-program, answering GETPIN with entries of URL gpg://<KEYGRIP>
+#[derive(Args)]
+pub struct SubPinentryArgs {
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// Real pinentry to use when no matching entry is found, e.g. /usr/bin/pinentry-curses
+    #[clap(long, value_parser, env = "GIT_CREDENTIAL_KEEPASSXC_PINENTRY_FALLBACK")]
+    pub fallback: Option<String>,
+    // options passed by gpg-agent, forwarded to the fallback pinentry
+    #[clap(long, value_parser, hide = true)]
+    pub display: Option<String>,
+    #[clap(long, value_parser, hide = true)]
+    pub ttyname: Option<String>,
+    #[clap(long, value_parser, hide = true)]
+    pub ttytype: Option<String>,
+    #[clap(long, value_parser, hide = true)]
+    pub lc_ctype: Option<String>,
+    #[clap(long, value_parser, hide = true)]
+    pub lc_messages: Option<String>,
+}
+
+impl SubPinentryArgs {
+    pub fn fallback_args(&self) -> Vec<String> {
+        [
+            ("--display", &self.display),
+            ("--ttyname", &self.ttyname),
+            ("--ttytype", &self.ttytype),
+            ("--lc-ctype", &self.lc_ctype),
+            ("--lc-messages", &self.lc_messages),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| [name.to_string(), v.clone()]))
+        .flatten()
+        .collect()
+    }
+}
+
+/// Run a command with credentials in its environment
+#[derive(Args)]
+pub struct SubExecArgs {
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// URL(s) to get credentials for
+    #[clap(long, value_parser, required = true)]
+    pub url: Vec<String>,
+    /// Environment variable to set, e.g. TOKEN=password, or DB_USER=2:username for the 2nd URL.
+    /// Fields are username, password, totp, name, uuid or advanced string fields
+    #[clap(long, value_parser)]
+    pub env: Vec<String>,
+    /// Command to run
+    #[clap(value_parser, required = true, last = true)]
+    pub command: Vec<String>,
+}
+
+/// Render a template with references like {{ kpxc "<URL>" "<FIELD>" }} and {{ totp "<URL>" }}
+#[derive(Args)]
+pub struct SubInjectArgs {
+    #[clap(flatten)]
+    pub filters: EntryFilterArgs,
+    /// Write to this file (mode 0600) instead of standard output
+    #[clap(short, long, value_parser)]
+    pub output: Option<String>,
+    /// Template file, standard input if omitted or -
+    #[clap(value_parser)]
+    pub template: Option<String>,
+}
+
+/// Keep the session with KeePassXC in background for other invocations to reuse (Unix only)
+#[derive(Args)]
+pub struct SubAgentArgs {
+    /// Exit after no requests for this many seconds, 0 to keep running
+    #[clap(long, value_parser, default_value_t = 900)]
+    pub idle_timeout: u64,
+}
+
+/// Print a line when databases are locked or unlocked
+#[derive(Args)]
+pub struct SubWatchArgs {
+    /// Print events in JSON
+    #[clap(long, value_parser)]
+    pub json: bool,
+    /// Command to run on each event, with KEEPASSXC_DATABASE_EVENT set to locked or unlocked
+    #[clap(long, value_parser)]
+    pub hook: Option<String>,
 }
 
 /// Associate git-credential-keepassxc with KeePassXC and configure preferences
@@ -370,6 +727,24 @@ pub struct UnlockOptions {
     pub interval: u64,
 }
 
+impl UnlockOptions {
+    /// Overall time to wait for databases to be unlocked, None if waiting indefinitely
+    pub fn timeout(&self) -> Option<Duration> {
+        if self.max_retries == 0 {
+            None
+        } else {
+            Some(
+                Duration::from_millis(self.interval)
+                    .saturating_mul(u32::try_from(self.max_retries).unwrap_or(u32::MAX)),
+            )
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval)
+    }
+}
+
 impl ValueParserFactory for UnlockOptions {
     type Parser = UnlockOptionsValueParser;
 
@@ -437,11 +812,29 @@ pub struct EntryFilters {
     pub kph: bool,
     pub groups: Vec<String>,
     pub git_groups: bool,
+    pub path_prefixes: bool,
+    pub http_auth: bool,
+    pub submit_url: Option<String>,
 }
 
 impl EntryFilters {
+    /// Command line options of the filters which differ from the defaults
+    pub fn non_default_options(&self) -> Vec<&'static str> {
+        [
+            (!self.groups.is_empty(), "--group"),
+            (self.git_groups, "--git-groups"),
+            (!self.kph, "--no-filter"),
+            (self.path_prefixes, "--path-prefixes"),
+            (self.http_auth, "--http-auth"),
+            (self.submit_url.is_some(), "--submit-url"),
+        ]
+        .into_iter()
+        .filter_map(|(set, option)| set.then_some(option))
+        .collect()
+    }
+
     pub fn has_non_default(&self) -> bool {
-        !self.kph || !self.groups.is_empty() || self.git_groups
+        !self.non_default_options().is_empty()
     }
 }
 
@@ -451,6 +844,9 @@ impl Default for EntryFilters {
             kph: true,
             groups: vec![],
             git_groups: false,
+            path_prefixes: false,
+            http_auth: false,
+            submit_url: None,
         }
     }
 }
@@ -468,6 +864,88 @@ pub trait HasLocalEntryFilters: HasEntryFilters {
             kph: main_entry_filters.kph && local_filters.kph,
             groups: effective_groups,
             git_groups: main_entry_filters.git_groups || local_filters.git_groups,
+            path_prefixes: main_entry_filters.path_prefixes || local_filters.path_prefixes,
+            http_auth: main_entry_filters.http_auth || local_filters.http_auth,
+            submit_url: main_entry_filters.submit_url.or(local_filters.submit_url),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::builder::TypedValueParser;
+    use clap::CommandFactory;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_00_multi_call_args() {
+        let args =
+            multi_call_args(["/usr/bin/docker-credential-keepassxc", "get"].map(OsString::from));
+        assert_eq!(
+            args,
+            ["/usr/bin/docker-credential-keepassxc", "docker", "get"]
+        );
+        let args =
+            multi_call_args(["cargo-credential-keepassxc", "--cargo-plugin"].map(OsString::from));
+        assert_eq!(
+            args,
+            ["cargo-credential-keepassxc", "cargo", "--cargo-plugin"]
+        );
+        let args = multi_call_args(["git-credential-keepassxc", "get"].map(OsString::from));
+        assert_eq!(args, ["git-credential-keepassxc", "get"]);
+    }
+
+    #[test]
+    fn test_01_unlock_timeout() {
+        let options = UnlockOptions::from_str("5,200").unwrap();
+        assert_eq!(options.timeout(), Some(Duration::from_secs(1)));
+        assert_eq!(options.interval(), Duration::from_millis(200));
+        let options = UnlockOptions::from_str("0").unwrap();
+        assert_eq!(options.timeout(), None);
+        assert_eq!(options.interval(), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_02_delete_rejected() {
+        // as Git runs helpers configured as 'keepassxc --delete-rejected'
+        let args =
+            MainArgs::try_parse_from(["git-credential-keepassxc", "--delete-rejected", "erase"])
+                .unwrap();
+        assert!(args.delete_rejected);
+        // the process environment is shared by tests running in parallel, so check what clap
+        // would do with the variable instead of setting it
+        let command = MainArgs::command();
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "delete-rejected")
+            .unwrap();
+        assert_eq!(
+            arg.get_env(),
+            Some(OsStr::new("GIT_CREDENTIAL_KEEPASSXC_DELETE_REJECTED"))
+        );
+        for (value, expected) in [("true", true), ("1", true), ("false", false), ("0", false)] {
+            let parsed = BoolishValueParser::new()
+                .parse_ref(&command, Some(arg), OsStr::new(value))
+                .unwrap();
+            assert_eq!(parsed, expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_03_non_default_entry_filters() {
+        assert!(!EntryFilters::default().has_non_default());
+        let args =
+            MainArgs::try_parse_from(["git-credential-keepassxc", "--http-auth", "get"]).unwrap();
+        assert!(args.entry_filters().has_non_default());
+        let args = MainArgs::try_parse_from([
+            "git-credential-keepassxc",
+            "--submit-url",
+            "https://example.com/login",
+            "get",
+        ])
+        .unwrap();
+        assert!(args.entry_filters().has_non_default());
+        assert_eq!(args.entry_filters().non_default_options(), ["--submit-url"]);
+    }
+}
@@ -1,19 +1,23 @@
 use crate::utils::base64;
 use crate::utils::callers::CurrentCaller;
+use crate::utils::url::UrlPolicy;
 #[allow(unused_imports)]
 use crate::{debug, error, info, warn};
 use aes_gcm::aead::generic_array::{typenum, GenericArray};
 use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
 #[cfg(all(test, feature = "yubikey"))]
 use mockall::automock;
 use serde::{de, Deserialize, Serialize};
+use sha2::Sha256;
 use std::cell::RefCell;
+use std::fmt;
 use std::fs;
 use std::io::prelude::*;
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
-use std::path::Path;
-use std::string::ToString;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "encryption")]
 use {
@@ -40,6 +44,7 @@ const HMAC_SHA1_RESPONSE_LENGTH: usize = 20usize;
 const AES_KEY_LENGTH: usize = 32usize;
 #[cfg(feature = "encryption")]
 const AES_NONCE_LENGTH: usize = 12usize;
+const QUARANTINE_KEY_LENGTH: usize = 32usize;
 
 type AesKey = GenericArray<u8, typenum::U32>;
 type AesNonce = GenericArray<u8, typenum::U12>;
@@ -55,6 +60,18 @@ pub struct Config {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     encrypted_callers: Vec<EncryptedProfile>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    quarantines: Vec<Quarantine>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    encrypted_quarantines: Vec<EncryptedProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quarantine_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_quarantine_key: Option<EncryptedProfile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    url_policies: Vec<UrlPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    docker_servers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     encryptions: Vec<Encryption>,
     #[cfg(feature = "encryption")]
     #[serde(skip)]
@@ -92,6 +109,15 @@ impl Config {
             config_path.as_ref().to_string_lossy()
         );
         let json = serde_json::to_string_pretty(self)?;
+        // write to a temporary file and rename it over the configuration, so that concurrent
+        // invocations never read a truncated file (follows symlinks, e.g. into dotfiles)
+        let config_path = fs::canonicalize(config_path.as_ref())
+            .unwrap_or_else(|_| config_path.as_ref().to_owned());
+        let temp_path = {
+            let mut temp_path = config_path.clone().into_os_string();
+            temp_path.push(format!(".{}.tmp", std::process::id()));
+            PathBuf::from(temp_path)
+        };
         let mut file_options = fs::OpenOptions::new();
         #[cfg(unix)]
         file_options.mode(DEFAULT_CONFIG_MODE);
@@ -99,20 +125,31 @@ impl Config {
             .create(true)
             .write(true)
             .truncate(true)
-            .open(config_path.as_ref())
+            .open(&temp_path)
             .with_context(|| {
                 format!(
                     "Failed to open configuration to {}",
-                    config_path.as_ref().to_string_lossy()
+                    temp_path.to_string_lossy()
                 )
             })?;
-
-        file.write_all(json.as_bytes()).with_context(|| {
-            format!(
+        let result = (|| -> Result<()> {
+            // keep permissions of the existing configuration
+            #[cfg(unix)]
+            if let Ok(metadata) = fs::metadata(&config_path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, &config_path)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.context(format!(
                 "Failed to write configuration to {}",
-                config_path.as_ref().to_string_lossy()
-            )
-        })?;
+                config_path.to_string_lossy()
+            )));
+        }
         Ok(())
     }
 
@@ -287,6 +324,102 @@ impl Config {
         Ok(decrypted_caller_indices.len())
     }
 
+    pub fn get_quarantines(&self) -> Result<Vec<Quarantine>> {
+        let mut quarantines: Vec<_> = self.quarantines.clone();
+        for encrypted_quarantine in &self.encrypted_quarantines {
+            let quarantine_json =
+                self.base64_decrypt(&encrypted_quarantine.data, &encrypted_quarantine.nonce);
+            if let Ok(quarantine_json) = quarantine_json {
+                quarantines.push(serde_json::from_str(quarantine_json.as_str())?);
+            } else {
+                warn!(
+                    "Failed to decrypt quarantine profile {}.. (omitted)",
+                    &encrypted_quarantine.data[..8]
+                );
+            }
+        }
+        Ok(quarantines)
+    }
+
+    pub fn count_quarantines(&self) -> usize {
+        self.quarantines.len() + self.encrypted_quarantines.len()
+    }
+
+    /// Secret of password fingerprints in quarantine profiles, if any quarantine has been created
+    pub fn get_quarantine_key(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(ref encrypted_key) = self.encrypted_quarantine_key {
+            let key = self.base64_decrypt(&encrypted_key.data, &encrypted_key.nonce)?;
+            return Ok(Some(base64::decode(key)?));
+        }
+        match self.quarantine_key {
+            Some(ref key) => Ok(Some(base64::decode(key)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_quarantine_key(&mut self) -> Result<Vec<u8>> {
+        if let Some(key) = self.get_quarantine_key()? {
+            return Ok(key);
+        }
+        let key: [u8; QUARANTINE_KEY_LENGTH] = rand::random();
+        self.store_quarantine_key(&key)?;
+        Ok(key.to_vec())
+    }
+
+    /// The key is encrypted along with quarantine profiles
+    fn store_quarantine_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = base64::encode(key);
+        if self.count_encrypted_databases() > 0 {
+            let (data, nonce) = self.base64_encrypt(&key)?;
+            self.encrypted_quarantine_key = Some(EncryptedProfile {
+                data,
+                nonce,
+                ..Default::default()
+            });
+            self.quarantine_key = None;
+        } else {
+            self.quarantine_key = Some(key);
+            self.encrypted_quarantine_key = None;
+        }
+        Ok(())
+    }
+
+    /// Replaces all quarantine profiles. They are encrypted if any database profile is encrypted.
+    pub fn set_quarantines(&mut self, quarantines: Vec<Quarantine>) -> Result<()> {
+        if let Some(key) = self.get_quarantine_key()? {
+            self.store_quarantine_key(&key)?;
+        }
+        let mut encrypted_quarantines = Vec::new();
+        if self.count_encrypted_databases() > 0 {
+            for quarantine in &quarantines {
+                let (data, nonce) = self.base64_encrypt(&serde_json::to_string(quarantine)?)?;
+                encrypted_quarantines.push(EncryptedProfile {
+                    data,
+                    nonce,
+                    ..Default::default()
+                });
+            }
+            self.quarantines.clear();
+        } else {
+            self.quarantines = quarantines;
+        }
+        self.encrypted_quarantines = encrypted_quarantines;
+        Ok(())
+    }
+
+    pub fn get_url_policies(&self) -> &[UrlPolicy] {
+        &self.url_policies
+    }
+
+    /// Server URLs stored by Docker, which are listed by 'docker list' subcommand
+    pub fn get_docker_servers(&self) -> &[String] {
+        &self.docker_servers
+    }
+
+    pub fn set_docker_servers(&mut self, docker_servers: Vec<String>) {
+        self.docker_servers = docker_servers;
+    }
+
     #[cfg(not(feature = "encryption"))]
     fn base64_decrypt(&self, _data: &str, _nonce: &AesNonce) -> Result<String> {
         error!("Enable encryption to use this feature");
@@ -436,13 +569,13 @@ impl Config {
     }
 
     #[cfg(not(feature = "encryption"))]
-    pub fn get_encryption_key(&self) -> Result<std::cell::Ref<Option<AesKey>>> {
+    pub fn get_encryption_key(&self) -> Result<std::cell::Ref<'_, Option<AesKey>>> {
         error!("Enable encryption to use this feature");
         Err(anyhow!("Encryption is not enabled in this build"))
     }
 
     #[cfg(feature = "encryption")]
-    pub fn get_encryption_key(&self) -> Result<std::cell::Ref<Option<AesKey>>> {
+    pub fn get_encryption_key(&self) -> Result<std::cell::Ref<'_, Option<AesKey>>> {
         if self.encryption_key.borrow().is_some() {
             return Ok(self.encryption_key.borrow());
         }
@@ -556,6 +689,50 @@ impl Database {
     }
 }
 
+/// A login rejected by Git, which is skipped by 'get' until the password changes or a 'store'
+/// request for the same entry comes in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Quarantine {
+    pub url: String,
+    pub uuid: String,
+    pub fingerprint: String,
+    pub since: u64,
+}
+
+impl Quarantine {
+    pub fn new<T: AsRef<str>>(url: T, uuid: T, password: T, key: &[u8]) -> Self {
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            url: url.as_ref().to_owned(),
+            uuid: uuid.as_ref().to_owned(),
+            fingerprint: password_fingerprint(uuid.as_ref(), password.as_ref(), key),
+            since,
+        }
+    }
+
+    pub fn matches<T: AsRef<str>>(&self, url: T, uuid: T, password: T, key: &[u8]) -> bool {
+        self.url == url.as_ref()
+            && self.uuid == uuid.as_ref()
+            && self.fingerprint == password_fingerprint(uuid.as_ref(), password.as_ref(), key)
+    }
+}
+
+/// Truncated HMAC-SHA256 of entry UUID and password, keyed with a random secret of the
+/// configuration so that weak passwords can't be guessed from the fingerprints offline
+fn password_fingerprint(uuid: &str, password: &str, key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(uuid.as_bytes());
+    mac.update(&[0u8]);
+    mac.update(password.as_bytes());
+    mac.finalize().into_bytes()[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 fn default_as_false() -> bool {
     false
 }
@@ -632,7 +809,7 @@ impl Encryption {
     }
 
     #[cfg(feature = "encryption")]
-    fn get_response(&self) -> Result<std::cell::Ref<Option<AesKey>>> {
+    fn get_response(&self) -> Result<std::cell::Ref<'_, Option<AesKey>>> {
         match self {
             #[cfg(not(feature = "yubikey"))]
             Encryption::ChallengeResponse { .. } => {
@@ -670,12 +847,12 @@ impl Encryption {
     }
 }
 
-impl ToString for Encryption {
-    fn to_string(&self) -> String {
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Encryption::ChallengeResponse {
                 slot, challenge, ..
-            } => format!("{}:{}:{}", self.method(), slot, challenge),
+            } => write!(f, "{}:{}:{}", self.method(), slot, challenge),
         }
     }
 }
@@ -768,6 +945,7 @@ impl MockYubiKeyTrait {
 }
 
 #[cfg(feature = "yubikey")]
+#[cfg_attr(test, allow(dead_code))]
 struct YubiKey {
     yubi: Yubico,
     device: yubico_manager::Device,
@@ -971,6 +1149,79 @@ mod tests {
         fs::remove_file(config_path).unwrap();
     }
 
+    #[test]
+    fn test_03_quarantine() {
+        let mut config = Config::new();
+        assert!(config.get_quarantine_key().unwrap().is_none());
+        let key = config.get_or_create_quarantine_key().unwrap();
+        assert_eq!(config.get_or_create_quarantine_key().unwrap(), key);
+        let quarantine = Quarantine::new("https://example.com", "mock uuid", "mock password", &key);
+        assert!(quarantine.matches("https://example.com", "mock uuid", "mock password", &key));
+        assert!(!quarantine.matches("https://example.com", "mock uuid", "new password", &key));
+        assert!(!quarantine.matches("https://example.org", "mock uuid", "mock password", &key));
+        assert!(!quarantine.matches(
+            "https://example.com",
+            "mock uuid",
+            "mock password",
+            b"other key"
+        ));
+        assert!(!quarantine.fingerprint.contains("mock password"));
+
+        config.set_quarantines(vec![quarantine.clone()]).unwrap();
+        assert_eq!(config.count_quarantines(), 1);
+        assert_eq!(config.get_quarantines().unwrap(), vec![quarantine]);
+        config.set_quarantines(vec![]).unwrap();
+        assert_eq!(config.count_quarantines(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "yubikey")]
+    fn test_04_encrypted_quarantine() {
+        let group = Group::new("mock group", "mock uuid");
+        let database = Database::new("mock database".to_owned(), generate_secret_key(), group);
+        let mut config = Config::new();
+        config.add_encryption("challenge-response").unwrap();
+        config.add_database(database, true).unwrap();
+
+        let key = config.get_or_create_quarantine_key().unwrap();
+        let quarantine = Quarantine::new("https://example.com", "mock uuid", "mock password", &key);
+        config.set_quarantines(vec![quarantine.clone()]).unwrap();
+        assert!(config.quarantines.is_empty());
+        assert_eq!(config.encrypted_quarantines.len(), 1);
+        assert!(config.quarantine_key.is_none());
+        assert!(config.encrypted_quarantine_key.is_some());
+        assert_eq!(config.get_quarantine_key().unwrap(), Some(key));
+        assert_eq!(config.get_quarantines().unwrap(), vec![quarantine]);
+    }
+
+    #[test]
+    fn test_05_write_replaces_config() {
+        let config_path = {
+            let mut temp = std::env::temp_dir();
+            temp.push(format!("{}.test_05.json", env!("CARGO_BIN_NAME")));
+            assert!(
+                !temp.exists(),
+                "Test configuration file {} already exists",
+                temp.to_string_lossy()
+            );
+            temp
+        };
+        let mut config = Config::new();
+        config.write_to(&config_path).unwrap();
+        let key = config.get_or_create_quarantine_key().unwrap();
+        let quarantine = Quarantine::new("https://example.com", "mock uuid", "mock password", &key);
+        config.set_quarantines(vec![quarantine.clone()]).unwrap();
+        config.write_to(&config_path).unwrap();
+
+        let config = Config::read_from(&config_path).unwrap();
+        assert_eq!(config.get_quarantines().unwrap(), vec![quarantine]);
+        let mut temp_path = config_path.clone().into_os_string();
+        temp_path.push(format!(".{}.tmp", std::process::id()));
+        assert!(!Path::new(&temp_path).exists());
+
+        fs::remove_file(config_path).unwrap();
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_github_15_00_new_config_file_permissions() {
@@ -0,0 +1,207 @@
+//! Docker credential helper protocol, see https://github.com/docker/docker-credential-helpers
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::git::GitCredentialMessage;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{erase_login_for, get_first_login_for, start_session, store_login_for, verify_caller};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Docker looks for this exact message to tell missing credentials from other errors
+const CREDENTIALS_NOT_FOUND: &str = "credentials not found in native keychain";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct DockerCredential {
+    #[serde(rename = "ServerURL")]
+    pub server_url: String,
+    pub username: String,
+    pub secret: String,
+}
+
+/// Server URLs from Docker may come without scheme, e.g. registry.example.com
+fn server_url_to_url<T: AsRef<str>>(server_url: T) -> String {
+    let server_url = server_url.as_ref();
+    if server_url.contains("://") {
+        server_url.to_owned()
+    } else {
+        format!("https://{}", server_url)
+    }
+}
+
+fn read_stdin() -> Result<String> {
+    let mut input = String::with_capacity(256);
+    io::stdin().read_to_string(&mut input)?;
+    Ok(input.trim().to_owned())
+}
+
+pub fn docker<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubDockerArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    match &args.command {
+        cli::DockerSubcommands::Get(_) => {
+            let server_url = read_stdin()?;
+            debug!("Docker server URL: {}", server_url);
+            let credential = get_credential(&config, &server_url, &entry_filters, unlock_options)?
+                .ok_or_else(|| {
+                    // Docker reads the error message from standard output
+                    println!("{}", CREDENTIALS_NOT_FOUND);
+                    anyhow!("No matching logins found")
+                })?;
+            io::stdout().write_all(serde_json::to_string(&credential)?.as_bytes())?;
+            Ok(())
+        }
+        cli::DockerSubcommands::Store(_) => {
+            let credential: DockerCredential = serde_json::from_str(&read_stdin()?)?;
+            debug!(
+                "Docker store request: {} ({})",
+                credential.server_url, credential.username
+            );
+            let git_req = GitCredentialMessage {
+                url: Some(server_url_to_url(&credential.server_url)),
+                username: Some(credential.username),
+                password: Some(credential.secret),
+                ..Default::default()
+            };
+            store_login_for(
+                config,
+                config_path.as_ref(),
+                unlock_options,
+                &entry_filters,
+                args.create_in.as_ref(),
+                git_req,
+            )?;
+
+            let mut config = Config::read_from(config_path.as_ref())?;
+            let mut docker_servers = config.get_docker_servers().to_vec();
+            if !docker_servers.contains(&credential.server_url) {
+                docker_servers.push(credential.server_url);
+                config.set_docker_servers(docker_servers);
+                config.write_to(config_path)?;
+            }
+            Ok(())
+        }
+        cli::DockerSubcommands::Erase(_) => {
+            let server_url = read_stdin()?;
+            debug!("Docker server URL: {}", server_url);
+            // same as Git, logins are quarantined rather than deleted, but only the one Docker
+            // has been given as Docker doesn't send the credential
+            match get_credential(&config, &server_url, &entry_filters, unlock_options)? {
+                Some(credential) => {
+                    let git_req = GitCredentialMessage {
+                        url: Some(server_url_to_url(&server_url)),
+                        username: Some(credential.username),
+                        password: Some(credential.secret),
+                        ..Default::default()
+                    };
+                    erase_login_for(
+                        config,
+                        config_path.as_ref(),
+                        unlock_options,
+                        &entry_filters,
+                        &cli::SubEraseArgs::default(),
+                        git_req,
+                    )?;
+                }
+                None => {
+                    info!("No matching logins found, nothing to erase");
+                }
+            }
+
+            let mut config = Config::read_from(config_path.as_ref())?;
+            let mut docker_servers = config.get_docker_servers().to_vec();
+            let num_docker_servers = docker_servers.len();
+            docker_servers.retain(|s| *s != server_url);
+            if docker_servers.len() != num_docker_servers {
+                config.set_docker_servers(docker_servers);
+                config.write_to(config_path)?;
+            }
+            Ok(())
+        }
+        cli::DockerSubcommands::List(_) => {
+            let mut usernames = BTreeMap::new();
+            for server_url in config.get_docker_servers() {
+                match get_credential(&config, server_url, &entry_filters, unlock_options) {
+                    Ok(Some(credential)) => {
+                        usernames.insert(credential.server_url, credential.username);
+                    }
+                    Ok(None) => {
+                        info!("No matching logins found for {}", server_url);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get logins for {}: {}", server_url, e);
+                    }
+                }
+            }
+            io::stdout().write_all(serde_json::to_string(&usernames)?.as_bytes())?;
+            Ok(())
+        }
+    }
+}
+
+fn get_credential(
+    config: &Config,
+    server_url: &str,
+    entry_filters: &EntryFilters,
+    unlock_options: &Option<UnlockOptions>,
+) -> Result<Option<DockerCredential>> {
+    let url = normalise_url(server_url_to_url(server_url), config.get_url_policies());
+    let (client_id, _, _) = start_session()?;
+    let login_entry = get_first_login_for(
+        config,
+        &client_id,
+        &url,
+        None,
+        entry_filters,
+        unlock_options,
+    )?;
+    Ok(login_entry.map(|login_entry| DockerCredential {
+        server_url: server_url.to_owned(),
+        username: login_entry.login,
+        secret: login_entry.password,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_server_url_to_url() {
+        assert_eq!(
+            server_url_to_url("registry.example.com"),
+            "https://registry.example.com"
+        );
+        assert_eq!(
+            server_url_to_url("https://index.docker.io/v1/"),
+            "https://index.docker.io/v1/"
+        );
+    }
+
+    #[test]
+    fn test_01_credential_json() {
+        let credential: DockerCredential = serde_json::from_str(
+            r#"{"ServerURL":"registry.example.com","Username":"foo","Secret":"bar"}"#,
+        )
+        .unwrap();
+        assert_eq!(credential.server_url, "registry.example.com");
+        assert_eq!(credential.username, "foo");
+        assert_eq!(credential.secret, "bar");
+        assert_eq!(
+            serde_json::to_string(&credential).unwrap(),
+            r#"{"ServerURL":"registry.example.com","Username":"foo","Secret":"bar"}"#
+        );
+    }
+}
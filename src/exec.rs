@@ -0,0 +1,152 @@
+//! Runs a command with credentials in its environment, so that secrets never go through stdout
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, get_login_field_for, start_session, verify_caller};
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, PartialEq, Eq)]
+struct EnvMapping {
+    name: String,
+    // index of --url
+    url_index: usize,
+    field: String,
+}
+
+impl EnvMapping {
+    /// NAME=FIELD for the first URL, or NAME=N:FIELD for the Nth one
+    fn parse<T: AsRef<str>>(spec: T, num_urls: usize) -> Result<Self> {
+        let spec = spec.as_ref();
+        let (name, value) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| anyhow!("Invalid environment variable mapping {}", spec))?;
+        let (url_index, field) = match value.split_once(':') {
+            Some((index, field))
+                if !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                let index: usize = index.parse()?;
+                if index == 0 || index > num_urls {
+                    return Err(anyhow!(
+                        "URL #{} in {} is out of range, {} URL(s) given",
+                        index,
+                        spec,
+                        num_urls
+                    ));
+                }
+                (index - 1, field)
+            }
+            _ => (0, value),
+        };
+        if field.is_empty() {
+            return Err(anyhow!("Field is missing in {}", spec));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            url_index,
+            field: field.to_owned(),
+        })
+    }
+}
+
+pub fn exec<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubExecArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let mappings = args
+        .env
+        .iter()
+        .map(|spec| EnvMapping::parse(spec, args.url.len()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // one session for all URLs
+    let (client_id, _, _) = start_session()?;
+    let mut login_entries = Vec::with_capacity(args.url.len());
+    for url in &args.url {
+        let url = normalise_url(url, config.get_url_policies());
+        let login_entry = get_first_login_for(
+            &config,
+            &client_id,
+            &url,
+            None,
+            &entry_filters,
+            unlock_options,
+        )?
+        .ok_or_else(|| anyhow!("No matching logins found for {}", url))?;
+        login_entries.push(login_entry);
+    }
+
+    let mut command = Command::new(&args.command[0]);
+    command.args(&args.command[1..]);
+    for mapping in &mappings {
+        let login_entry = &login_entries[mapping.url_index];
+        let value = get_login_field_for(client_id.as_str(), login_entry, mapping.field.as_str())?;
+        debug!(
+            "Setting {} to {} of {}",
+            mapping.name, mapping.field, login_entry.name
+        );
+        command.env(&mapping.name, value);
+    }
+
+    info!("Running {}", args.command[0]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // only returns on failure
+        let e = command.exec();
+        Err(anyhow!("Failed to run {}, {}", args.command[0], e))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command.status()?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_env_mapping() {
+        assert_eq!(
+            EnvMapping::parse("TOKEN=password", 1).unwrap(),
+            EnvMapping {
+                name: "TOKEN".to_owned(),
+                url_index: 0,
+                field: "password".to_owned(),
+            }
+        );
+        assert_eq!(
+            EnvMapping::parse("DB_USER=2:username", 2).unwrap(),
+            EnvMapping {
+                name: "DB_USER".to_owned(),
+                url_index: 1,
+                field: "username".to_owned(),
+            }
+        );
+        assert_eq!(
+            EnvMapping::parse("API_KEY=KPH: api_key", 1).unwrap(),
+            EnvMapping {
+                name: "API_KEY".to_owned(),
+                url_index: 0,
+                field: "KPH: api_key".to_owned(),
+            }
+        );
+        assert!(EnvMapping::parse("TOKEN", 1).is_err());
+        assert!(EnvMapping::parse("=password", 1).is_err());
+        assert!(EnvMapping::parse("TOKEN=", 1).is_err());
+        assert!(EnvMapping::parse("TOKEN=2:password", 1).is_err());
+        assert!(EnvMapping::parse("TOKEN=0:password", 1).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod format;
+
 #[allow(unused_imports)]
 use crate::{debug, error, info, warn};
 use serde::Serialize;
@@ -5,8 +7,14 @@ use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, Read};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 const KPXC_ADVANCED_FIELD_PREFIX: &str = "KPH: ";
+// credential attributes that are stored as advanced fields but have first-class keys in Git
+const CREDENTIAL_ATTRIBUTE_FIELDS: &[&str] = &["password_expiry_utc", "oauth_refresh_token"];
+// capabilities announced back to Git if Git has advertised them as well
+const SUPPORTED_CAPABILITIES: &[&str] = &["authtype"];
 
 #[derive(Debug)]
 pub struct GitMessageParsingError {
@@ -29,6 +37,9 @@ impl std::error::Error for GitMessageParsingError {}
 macro_rules! message_from_to_string {
     ($vis:vis struct $name:ident {
         $($field_vis:vis $field_name:ident: $field_type:ty,)*
+    }
+    arrays {
+        $($array_vis:vis $array_name:ident: $array_type:ty,)*
     }) => {
         #[derive(Default, Serialize, Debug)]
         $vis struct $name {
@@ -36,31 +47,48 @@ macro_rules! message_from_to_string {
                 #[serde(skip_serializing_if = "Option::is_none")]
                 $field_vis $field_name: $field_type,
             )*
+            $(
+                #[serde(skip_serializing_if = "Vec::is_empty")]
+                $array_vis $array_name: $array_type,
+            )*
             #[serde(skip_serializing_if = "Option::is_none")]
             pub string_fields: Option<HashMap<String, String>>,
         }
 
-        impl ToString for $name {
-            fn to_string(&self) -> String {
-                let mut msg = String::new();
+        impl $name {
+            /// Key-value pairs in the order they are sent to Git, array keys end with '[]'
+            pub fn to_pairs(&self) -> Vec<(String, String)> {
+                let mut pairs = Vec::new();
                 $(
                     if let Some(ref value) = self.$field_name {
-                        msg.push_str(stringify!($field_name));
-                        msg.push('=');
-                        msg.push_str(value);
-                        msg.push('\n');
+                        pairs.push((stringify!($field_name).to_owned(), value.clone()));
+                    }
+                )*
+                $(
+                    for value in &self.$array_name {
+                        pairs.push((concat!(stringify!($array_name), "[]").to_owned(), value.clone()));
                     }
                 )*
                 if let Some(ref string_fields) = self.string_fields {
                     for (key, value) in string_fields {
-                        msg.push_str(key);
-                        msg.push('=');
-                        msg.push_str(value);
-                        msg.push('\n');
+                        pairs.push((key.clone(), value.clone()));
                     }
                 }
+                pairs
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut msg = String::new();
+                for (key, value) in self.to_pairs() {
+                    msg.push_str(&key);
+                    msg.push('=');
+                    msg.push_str(&value);
+                    msg.push('\n');
+                }
                 msg.push('\n');
-                msg
+                f.write_str(&msg)
             }
         }
 
@@ -84,6 +112,16 @@ macro_rules! message_from_to_string {
                             stringify!($field_name) => {
                                 msg.$field_name = Some(pair[split_at + 1..].to_owned());
                             },
+                        )*
+                        $(
+                            // an empty value resets the array
+                            concat!(stringify!($array_name), "[]") => {
+                                if split_at + 1 == pair.len() {
+                                    msg.$array_name.clear();
+                                } else {
+                                    msg.$array_name.push(pair[split_at + 1..].to_owned());
+                                }
+                            },
                         )*
                             _ => {},
                     }
@@ -101,8 +139,17 @@ message_from_to_string!(
         pub path: Option<String>,
         pub username: Option<String>,
         pub password: Option<String>,
+        pub authtype: Option<String>,
+        pub credential: Option<String>,
+        pub password_expiry_utc: Option<String>,
+        pub oauth_refresh_token: Option<String>,
         pub url: Option<String>,
         pub totp: Option<String>,
+        pub quit: Option<String>,
+    }
+    arrays {
+        pub capability: Vec<String>,
+        pub wwwauth: Vec<String>,
     }
 );
 
@@ -125,6 +172,13 @@ impl GitCredentialMessage {
                     || &key[..KPXC_ADVANCED_FIELD_PREFIX.len()] != KPXC_ADVANCED_FIELD_PREFIX
                 {
                     warn!("Ignored advanced field {} due to malformed key", key);
+                } else if CREDENTIAL_ATTRIBUTE_FIELDS
+                    .contains(&&key[KPXC_ADVANCED_FIELD_PREFIX.len()..])
+                {
+                    debug!(
+                        "Skipped advanced field {} as it's a credential attribute",
+                        key
+                    );
                 } else {
                     result.insert(
                         key[KPXC_ADVANCED_FIELD_PREFIX.len()..].to_string(),
@@ -136,6 +190,88 @@ impl GitCredentialMessage {
         self.string_fields = Some(result);
     }
 
+    /// Fills in password_expiry_utc and oauth_refresh_token from the corresponding advanced fields,
+    /// e.g. 'KPH: password_expiry_utc'
+    pub fn set_credential_attributes(&mut self, login_entry_fields: &[HashMap<String, String>]) {
+        let find_field = |name: &str| {
+            let key = KPXC_ADVANCED_FIELD_PREFIX.to_owned() + name;
+            login_entry_fields
+                .iter()
+                .find_map(|login_entry_field| login_entry_field.get(&key).cloned())
+        };
+        self.password_expiry_utc = find_field("password_expiry_utc");
+        self.oauth_refresh_token = find_field("oauth_refresh_token");
+    }
+
+    /// Whether password_expiry_utc (Unix timestamp in seconds) is in the past
+    pub fn is_password_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        match self.password_expiry_utc.as_deref().map(u64::from_str) {
+            Some(Ok(expiry)) => expiry <= now,
+            Some(Err(_)) => {
+                warn!(
+                    "Ignored malformed password_expiry_utc {}",
+                    self.password_expiry_utc.as_ref().unwrap()
+                );
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// password_expiry_utc in RFC 3339, e.g. 2023-11-14T22:13:20Z
+    pub fn password_expiry_rfc3339(&self) -> Option<String> {
+        let password_expiry_utc = self.password_expiry_utc.as_ref()?;
+        let result = password_expiry_utc
+            .parse::<i64>()
+            .map_err(anyhow::Error::from)
+            .and_then(|timestamp| {
+                Ok(OffsetDateTime::from_unix_timestamp(timestamp)?.format(&Rfc3339)?)
+            });
+        match result {
+            Ok(expiry) => Some(expiry),
+            Err(e) => {
+                warn!(
+                    "Ignored malformed password_expiry_utc {}: {}",
+                    password_expiry_utc, e
+                );
+                None
+            }
+        }
+    }
+
+    pub fn has_capability<T: AsRef<str>>(&self, capability: T) -> bool {
+        self.capability.iter().any(|c| c == capability.as_ref())
+    }
+
+    /// Keeps only the capabilities advertised by Git which are also supported by us, so that the
+    /// response announces them back
+    pub fn negotiate_capabilities(&mut self) {
+        self.capability
+            .retain(|c| SUPPORTED_CAPABILITIES.contains(&c.as_str()));
+        debug!("Negotiated capabilities: {:?}", self.capability);
+    }
+
+    /// Lower-cased authentication schemes from the WWW-Authenticate headers sent by the server,
+    /// e.g. 'basic' and 'bearer'
+    pub fn www_auth_schemes(&self) -> Vec<String> {
+        let mut schemes: Vec<String> = Vec::new();
+        for header in &self.wwwauth {
+            let scheme = header
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            if !scheme.is_empty() && !schemes.contains(&scheme) {
+                schemes.push(scheme);
+            }
+        }
+        schemes
+    }
+
     pub fn get_url(&self) -> anyhow::Result<String> {
         if let Some(ref url_string) = self.url {
             Ok(url_string.clone())
@@ -155,6 +291,24 @@ impl GitCredentialMessage {
     }
 }
 
+/// Returns the URL itself followed by its path prefixes down to the bare host, e.g.
+/// https://example.com/foo/bar.git, https://example.com/foo, https://example.com
+pub fn url_path_prefixes<T: AsRef<str>>(url: T) -> Vec<String> {
+    let url = url.as_ref();
+    let host_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    let path_start = match url[host_start..].find('/') {
+        Some(i) => host_start + i,
+        None => return vec![url.to_owned()],
+    };
+    let mut results = vec![url.to_owned()];
+    let mut path = url[path_start..].trim_end_matches('/');
+    while let Some(i) = path.rfind('/') {
+        path = path[..i].trim_end_matches('/');
+        results.push(url[..path_start].to_owned() + path);
+    }
+    results
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +361,122 @@ mod tests {
             message.to_string()
         );
     }
+
+    #[test]
+    fn test_04_credential_attributes() {
+        let string = "username=foo\npassword=bar\npassword_expiry_utc=1684416000\noauth_refresh_token=baz\nurl=http://example.com\n".to_owned();
+        let message = GitCredentialMessage::from_str(string.as_str()).unwrap();
+        assert_eq!(
+            message.password_expiry_utc.as_ref().unwrap().as_str(),
+            "1684416000"
+        );
+        assert_eq!(
+            message.oauth_refresh_token.as_ref().unwrap().as_str(),
+            "baz"
+        );
+        assert!(message.is_password_expired());
+        assert_eq!(string + "\n", message.to_string());
+    }
+
+    #[test]
+    fn test_05_credential_attributes_from_advanced_fields() {
+        let string = "username=foo\nurl=http://example.com\n".to_owned();
+        let mut message = GitCredentialMessage::from_str(string.as_str()).unwrap();
+        let advanced_fields: Vec<HashMap<String, String>> = [
+            ("KPH: password_expiry_utc", "99999999999"),
+            ("KPH: oauth_refresh_token", "baz"),
+            ("KPH: advanced_field1", "foo"),
+        ]
+        .iter()
+        .map(|(key, value)| {
+            let mut advanced_field = HashMap::new();
+            advanced_field.insert(key.to_string(), value.to_string());
+            advanced_field
+        })
+        .collect();
+        message.set_credential_attributes(&advanced_fields);
+        message.set_string_fields(&advanced_fields);
+        assert!(!message.is_password_expired());
+        assert_eq!(
+            "username=foo\npassword_expiry_utc=99999999999\noauth_refresh_token=baz\nurl=http://example.com\nadvanced_field1=foo\n\n",
+            message.to_string()
+        );
+    }
+
+    #[test]
+    fn test_06_array_fields() {
+        let string = "url=http://example.com\ncapability[]=authtype\ncapability[]=state\nwwwauth[]=Basic realm=\"example\"\nwwwauth[]=Bearer authorize_uri=\"https://example.com/oauth\"\n".to_owned();
+        let message = GitCredentialMessage::from_str(string.as_str()).unwrap();
+        assert_eq!(message.capability, vec!["authtype", "state"]);
+        assert_eq!(message.wwwauth.len(), 2);
+        assert_eq!(message.www_auth_schemes(), vec!["basic", "bearer"]);
+        assert_eq!(string + "\n", message.to_string());
+    }
+
+    #[test]
+    fn test_07_array_fields_reset() {
+        let string = "url=http://example.com\nwwwauth[]=Basic\nwwwauth[]=\nwwwauth[]=Bearer\n";
+        let message = GitCredentialMessage::from_str(string).unwrap();
+        assert_eq!(message.wwwauth, vec!["Bearer"]);
+    }
+
+    #[test]
+    fn test_08_negotiate_capabilities() {
+        let string = "url=http://example.com\ncapability[]=foo\n";
+        let mut message = GitCredentialMessage::from_str(string).unwrap();
+        message.negotiate_capabilities();
+        assert!(message.capability.is_empty());
+        assert_eq!("url=http://example.com\n\n", message.to_string());
+    }
+
+    #[test]
+    fn test_09_authtype_credential() {
+        let string = "url=http://example.com\ncapability[]=authtype\n";
+        let mut message = GitCredentialMessage::from_str(string).unwrap();
+        assert!(message.has_capability("authtype"));
+        message.negotiate_capabilities();
+        message.authtype = Some("Bearer".to_owned());
+        message.credential = Some("foo".to_owned());
+        assert_eq!(
+            "authtype=Bearer\ncredential=foo\nurl=http://example.com\ncapability[]=authtype\n\n",
+            message.to_string()
+        );
+    }
+
+    #[test]
+    fn test_10_url_path_prefixes() {
+        assert_eq!(
+            url_path_prefixes("https://example.com/foo/bar.git"),
+            vec![
+                "https://example.com/foo/bar.git",
+                "https://example.com/foo",
+                "https://example.com"
+            ]
+        );
+        assert_eq!(
+            url_path_prefixes("https://example.com/foo/"),
+            vec!["https://example.com/foo/", "https://example.com"]
+        );
+        assert_eq!(
+            url_path_prefixes("https://example.com/"),
+            vec!["https://example.com/"]
+        );
+        assert_eq!(
+            url_path_prefixes("https://example.com"),
+            vec!["https://example.com"]
+        );
+    }
+
+    #[test]
+    fn test_11_password_expiry_rfc3339() {
+        let mut message = GitCredentialMessage::default();
+        assert!(message.password_expiry_rfc3339().is_none());
+        message.password_expiry_utc = Some("1700000000".to_owned());
+        assert_eq!(
+            message.password_expiry_rfc3339().as_deref(),
+            Some("2023-11-14T22:13:20Z")
+        );
+        message.password_expiry_utc = Some("foo".to_owned());
+        assert!(message.password_expiry_rfc3339().is_none());
+    }
 }
@@ -0,0 +1,314 @@
+use super::GitCredentialMessage;
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+// prefix of variable names so that eval-ing the output can't overwrite e.g. PATH or HOME
+const ENV_PREFIX: &str = "GIT_CREDENTIAL_";
+
+/// How a credential message is printed for the user or a script
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// key=value lines understood by Git
+    #[default]
+    Git,
+    Json,
+    /// export GIT_CREDENTIAL_KEY='value' lines for POSIX shells
+    Export,
+    /// GIT_CREDENTIAL_KEY="value" lines for .env files
+    Dotenv,
+    /// A single .netrc machine stanza
+    Netrc,
+    /// Bare value of a single field, e.g. password
+    Field(String),
+    /// User-supplied template with {field} placeholders, e.g. {username}:{password}
+    Template(String),
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "git" => Ok(Self::Git),
+            "json" => Ok(Self::Json),
+            "export" => Ok(Self::Export),
+            "dotenv" => Ok(Self::Dotenv),
+            "netrc" => Ok(Self::Netrc),
+            _ if s.contains('{') => Ok(Self::Template(s.to_owned())),
+            _ => Err(format!(
+                "Unknown format {}, expected git, json, export, dotenv, netrc or a template like '{{username}}:{{password}}'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Git => f.write_str("git"),
+            Self::Json => f.write_str("json"),
+            Self::Export => f.write_str("export"),
+            Self::Dotenv => f.write_str("dotenv"),
+            Self::Netrc => f.write_str("netrc"),
+            Self::Field(field) => write!(f, "field {}", field),
+            Self::Template(template) => f.write_str(template),
+        }
+    }
+}
+
+impl GitCredentialMessage {
+    /// Serialises the message in the given format, escaping values as required by the target
+    pub fn format(&self, format: &OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Git => Ok(self.to_string()),
+            OutputFormat::Json => Ok(serde_json::to_string(self)?),
+            OutputFormat::Export => Ok(self
+                .env_pairs()
+                .map(|(key, value)| format!("export {}={}\n", key, shell_quote(&value)))
+                .collect()),
+            OutputFormat::Dotenv => Ok(self
+                .env_pairs()
+                .map(|(key, value)| format!("{}={}\n", key, dotenv_quote(&value)))
+                .collect()),
+            OutputFormat::Netrc => Ok(self.to_netrc()),
+            OutputFormat::Field(field) => Ok(self
+                .get_field(field)
+                .ok_or_else(|| anyhow!("Field {} not found", field))?
+                + "\n"),
+            OutputFormat::Template(template) => Ok(self.render_template(template)? + "\n"),
+        }
+    }
+
+    /// Value of a field as it would be sent to Git, arrays can be referred to with or without '[]'
+    fn get_field<T: AsRef<str>>(&self, field: T) -> Option<String> {
+        let field = field.as_ref();
+        self.to_pairs()
+            .into_iter()
+            .find(|(key, _)| key == field || key.strip_suffix("[]") == Some(field))
+            .map(|(_, value)| value)
+    }
+
+    // only a fixed set of fields is exported, advanced fields may have arbitrary names
+    fn env_pairs(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        [
+            ("PROTOCOL", &self.protocol),
+            ("HOST", &self.host),
+            ("PATH", &self.path),
+            ("USERNAME", &self.username),
+            ("PASSWORD", &self.password),
+            ("AUTHTYPE", &self.authtype),
+            ("CREDENTIAL", &self.credential),
+            ("PASSWORD_EXPIRY_UTC", &self.password_expiry_utc),
+            ("OAUTH_REFRESH_TOKEN", &self.oauth_refresh_token),
+            ("URL", &self.url),
+            ("TOTP", &self.totp),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((ENV_PREFIX.to_owned() + name, value.clone()?)))
+    }
+
+    fn to_netrc(&self) -> String {
+        let mut netrc = match self.netrc_machine() {
+            Some(machine) => format!("machine {}", netrc_quote(&machine)),
+            None => "default".to_owned(),
+        };
+        if let Some(ref username) = self.username {
+            netrc.push_str(" login ");
+            netrc.push_str(&netrc_quote(username));
+        }
+        if let Some(password) = self.password.as_ref().or(self.credential.as_ref()) {
+            netrc.push_str(" password ");
+            netrc.push_str(&netrc_quote(password));
+        }
+        netrc.push('\n');
+        netrc
+    }
+
+    // host name without user info and port
+    fn netrc_machine(&self) -> Option<String> {
+        let host = match (&self.host, &self.url) {
+            (Some(host), _) => host.as_str(),
+            (None, Some(url)) => {
+                let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+                rest.split(['/', '?', '#']).next().unwrap_or_default()
+            }
+            (None, None) => return None,
+        };
+        let host = host.rsplit_once('@').map(|(_, host)| host).unwrap_or(host);
+        let host = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => &host[..i],
+            _ => host,
+        };
+        Some(host.to_owned()).filter(|host| !host.is_empty())
+    }
+
+    fn render_template<T: AsRef<str>>(&self, template: T) -> Result<String> {
+        let mut result = String::new();
+        let mut chars = template.as_ref().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    result.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    result.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(anyhow!("Unterminated {{{} in template", field)),
+                        }
+                    }
+                    let value = self
+                        .get_field(&field)
+                        .ok_or_else(|| anyhow!("Field {} in template not found", field))?;
+                    result.push_str(&value);
+                }
+                '}' => return Err(anyhow!("Unmatched }} in template")),
+                _ => result.push(c),
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// dotenv loaders expand variables and commands in double-quoted values
+fn dotenv_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '"' => quoted.push_str(r#"\""#),
+            '$' => quoted.push_str(r"\$"),
+            '`' => quoted.push_str(r"\`"),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// netrc tokens are separated by white spaces, curl and others accept double-quoted tokens
+fn netrc_quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return value.to_owned();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '"' => quoted.push_str(r#"\""#),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            '\t' => quoted.push_str(r"\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> GitCredentialMessage {
+        GitCredentialMessage::from_str(
+            "protocol=https\nhost=example.com:8443\nusername=foo\npassword=it's a \"secret\"\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_00_parse_format() {
+        assert_eq!(OutputFormat::from_str("netrc"), Ok(OutputFormat::Netrc));
+        assert_eq!(
+            OutputFormat::from_str("{username}:{password}"),
+            Ok(OutputFormat::Template("{username}:{password}".to_owned()))
+        );
+        assert!(OutputFormat::from_str("yaml").is_err());
+    }
+
+    #[test]
+    fn test_01_export_and_dotenv() {
+        let message = message();
+        assert_eq!(
+            message.format(&OutputFormat::Export).unwrap(),
+            "export GIT_CREDENTIAL_PROTOCOL='https'\nexport GIT_CREDENTIAL_HOST='example.com:8443'\nexport GIT_CREDENTIAL_USERNAME='foo'\nexport GIT_CREDENTIAL_PASSWORD='it'\\''s a \"secret\"'\n"
+        );
+        assert_eq!(
+            message.format(&OutputFormat::Dotenv).unwrap(),
+            "GIT_CREDENTIAL_PROTOCOL=\"https\"\nGIT_CREDENTIAL_HOST=\"example.com:8443\"\nGIT_CREDENTIAL_USERNAME=\"foo\"\nGIT_CREDENTIAL_PASSWORD=\"it's a \\\"secret\\\"\"\n"
+        );
+
+        let mut message = GitCredentialMessage::from_str(
+            "protocol=https\nhost=example.com\npassword=$HOME`id`\ncapability[]=authtype\n",
+        )
+        .unwrap();
+        message.string_fields = Some(
+            [("path".to_owned(), "/tmp".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            message.format(&OutputFormat::Dotenv).unwrap(),
+            "GIT_CREDENTIAL_PROTOCOL=\"https\"\nGIT_CREDENTIAL_HOST=\"example.com\"\nGIT_CREDENTIAL_PASSWORD=\"\\$HOME\\`id\\`\"\n"
+        );
+    }
+
+    #[test]
+    fn test_02_netrc() {
+        assert_eq!(
+            message().format(&OutputFormat::Netrc).unwrap(),
+            "machine example.com login foo password \"it's a \\\"secret\\\"\"\n"
+        );
+        let message =
+            GitCredentialMessage::from_str("url=https://bar@example.com/foo\npassword=bar\n")
+                .unwrap();
+        assert_eq!(
+            message.format(&OutputFormat::Netrc).unwrap(),
+            "machine example.com password bar\n"
+        );
+    }
+
+    #[test]
+    fn test_03_field_and_template() {
+        let message = message();
+        assert_eq!(
+            message
+                .format(&OutputFormat::Field("username".to_owned()))
+                .unwrap(),
+            "foo\n"
+        );
+        assert!(message
+            .format(&OutputFormat::Field("totp".to_owned()))
+            .is_err());
+        assert_eq!(
+            message
+                .format(&OutputFormat::Template("{{{username}}}@{host}".to_owned()))
+                .unwrap(),
+            "{foo}@example.com:8443\n"
+        );
+        assert!(message
+            .format(&OutputFormat::Template("{totp}".to_owned()))
+            .is_err());
+        assert!(message
+            .format(&OutputFormat::Template("{username}:{password".to_owned()))
+            .is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Renders templates with references to KeePassXC entries, e.g. {{ kpxc "https://example.com" "password" }}
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, get_login_field_for, start_session, verify_caller};
+use anyhow::{anyhow, Context, Result};
+use std::collections::{hash_map::Entry, HashMap};
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+#[cfg(unix)]
+const OUTPUT_FILE_MODE: u32 = 0o600;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Reference { url: String, field: String },
+}
+
+/// Quoted strings separated by whitespace, with \" and \\ escapes
+fn parse_arguments(s: &str) -> Result<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut chars = s.trim().chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c != '"' {
+            return Err(anyhow!("Arguments must be quoted"));
+        }
+        let mut argument = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(c) if c == '"' || c == '\\' => argument.push(c),
+                    Some(c) => return Err(anyhow!("Unknown escape sequence \\{}", c)),
+                    None => return Err(anyhow!("Unterminated string")),
+                },
+                Some(c) => argument.push(c),
+                None => return Err(anyhow!("Unterminated string")),
+            }
+        }
+        arguments.push(argument);
+    }
+    Ok(arguments)
+}
+
+fn parse_expression(expression: &str) -> Result<Option<Segment>> {
+    let expression = expression.trim();
+    let (function, arguments) = expression
+        .split_once(char::is_whitespace)
+        .unwrap_or((expression, ""));
+    let segment = match function {
+        "kpxc" => match &parse_arguments(arguments)?[..] {
+            [url, field] => Segment::Reference {
+                url: url.clone(),
+                field: field.clone(),
+            },
+            _ => return Err(anyhow!("kpxc takes a URL and a field")),
+        },
+        "totp" => match &parse_arguments(arguments)?[..] {
+            [url] => Segment::Reference {
+                url: url.clone(),
+                field: "totp".to_owned(),
+            },
+            _ => return Err(anyhow!("totp takes a URL")),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(segment))
+}
+
+fn parse_template(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        let expression = &rest[start + 2..end];
+        match parse_expression(expression)
+            .with_context(|| format!("Failed to parse {{{{{}}}}}", expression))?
+        {
+            Some(segment) => {
+                text.push_str(&rest[..start]);
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(segment);
+            }
+            None => {
+                // other templating languages use double braces as well
+                warn!("Leaving {{{{{}}}}} as is", expression);
+                text.push_str(&rest[..end + 2]);
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+fn write_output<T: AsRef<Path>>(path: T, content: &str) -> Result<()> {
+    let mut file_options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    file_options.mode(OUTPUT_FILE_MODE);
+    let mut file = file_options
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path.as_ref())
+        .with_context(|| format!("Failed to open {}", path.as_ref().to_string_lossy()))?;
+    // mode above only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(OUTPUT_FILE_MODE))?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+pub fn inject<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubInjectArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let template = match args.template.as_deref() {
+        Some(path) if path != "-" => {
+            fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?
+        }
+        _ => {
+            let mut template = String::new();
+            io::stdin().read_to_string(&mut template)?;
+            template
+        }
+    };
+    let segments = parse_template(&template)?;
+
+    // resolve all references in one session
+    let (client_id, _, _) = start_session()?;
+    let mut login_entries = HashMap::new();
+    let mut values: HashMap<(&str, &str), String> = HashMap::new();
+    let mut output = String::with_capacity(template.len());
+    for segment in &segments {
+        let (url, field) = match segment {
+            Segment::Text(text) => {
+                output.push_str(text);
+                continue;
+            }
+            Segment::Reference { url, field } => (url.as_str(), field.as_str()),
+        };
+        if let Entry::Vacant(value_entry) = values.entry((url, field)) {
+            if !login_entries.contains_key(url) {
+                let normalised_url = normalise_url(url, config.get_url_policies());
+                let login_entry = get_first_login_for(
+                    &config,
+                    &client_id,
+                    &normalised_url,
+                    None,
+                    &entry_filters,
+                    unlock_options,
+                )?
+                .ok_or_else(|| anyhow!("No matching logins found for {}", normalised_url))?;
+                login_entries.insert(url, login_entry);
+            }
+            value_entry.insert(get_login_field_for(
+                client_id.as_str(),
+                &login_entries[url],
+                field,
+            )?);
+        }
+        output.push_str(&values[&(url, field)]);
+    }
+    info!(
+        "Resolved {} reference(s) from {} login(s)",
+        values.len(),
+        login_entries.len()
+    );
+
+    match args.output {
+        Some(ref path) => write_output(path, &output),
+        None => {
+            io::stdout().write_all(output.as_bytes())?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(url: &str, field: &str) -> Segment {
+        Segment::Reference {
+            url: url.to_owned(),
+            field: field.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_00_parse_template() {
+        let segments = parse_template(
+            "//registry.example.com/:_authToken={{ kpxc \"https://registry.example.com\" \"password\" }}\ncode={{totp \"https://vpn\"}}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Text("//registry.example.com/:_authToken=".to_owned()),
+                reference("https://registry.example.com", "password"),
+                Segment::Text("\ncode=".to_owned()),
+                reference("https://vpn", "totp"),
+                Segment::Text("\n".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_01_other_braces_left_as_is() {
+        let template = "name: {{ .Values.name }}\nunterminated {{ kpxc";
+        assert_eq!(
+            parse_template(template).unwrap(),
+            vec![Segment::Text(template.to_owned())]
+        );
+        assert_eq!(
+            parse_template(r#"{{ kpxc "https://a\"b" "KPH: c\\d" }}"#).unwrap(),
+            vec![reference("https://a\"b", "KPH: c\\d")]
+        );
+    }
+
+    #[test]
+    fn test_02_invalid_references() {
+        assert!(parse_template(r#"{{ kpxc "https://example.com" }}"#).is_err());
+        assert!(parse_template(r#"{{ kpxc https://example.com password }}"#).is_err());
+        assert!(parse_template(r#"{{ totp "https://example.com" "password" }}"#).is_err());
+        assert!(parse_template(r#"{{ kpxc "https://example.com" "pass }}"#).is_err());
+    }
+}
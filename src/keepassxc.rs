@@ -1,5 +1,6 @@
 pub mod errors;
 pub mod messages;
+pub mod version;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize, Deserialize, Default, Debug)]
@@ -1,4 +1,5 @@
 use super::messages::{GenericResponseWrapper, KeePassErrorCode};
+use super::version::{Feature, Version};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 
@@ -21,6 +22,23 @@ impl KeePassError {
     }
 }
 
+impl KeePassError {
+    pub fn is_no_logins_found(&self) -> bool {
+        matches!(
+            self.response.error_code,
+            Some(KeePassErrorCode::NoLoginsFound)
+        )
+    }
+
+    /// KeePassXC replies with this when it doesn't know the action, i.e. it's too old
+    pub fn is_action_unsupported(&self) -> bool {
+        matches!(
+            self.response.error_code,
+            Some(KeePassErrorCode::IncorrectAction)
+        )
+    }
+}
+
 impl Display for KeePassError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_str(self.message.as_str())?;
@@ -29,3 +47,44 @@ impl Display for KeePassError {
 }
 
 impl Error for KeePassError {}
+
+/// Raised before sending requests which the running KeePassXC is known not to support
+#[derive(Debug)]
+pub struct UnsupportedFeatureError {
+    pub feature: Feature,
+    pub version: Version,
+}
+
+impl Display for UnsupportedFeatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requires KeePassXC {} or later, but {} is running",
+            self.feature,
+            self.feature.min_version(),
+            self.version
+        )
+    }
+}
+
+impl Error for UnsupportedFeatureError {}
+
+/// Response which doesn't look like what the protocol describes, e.g. a wrapper without action or
+/// a message missing required fields
+#[derive(Debug)]
+pub struct MalformedResponseError {
+    pub what: &'static str,
+    pub source: serde_json::Error,
+}
+
+impl Display for MalformedResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed {} from KeePassXC: {}", self.what, self.source)
+    }
+}
+
+impl Error for MalformedResponseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
@@ -3,5 +3,6 @@ mod primitives;
 mod structs;
 
 pub use error_code::*;
+#[allow(unused_imports)]
 pub use primitives::*;
 pub use structs::*;
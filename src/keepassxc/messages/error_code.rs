@@ -40,9 +40,17 @@ impl<'de> Deserialize<'de> for KeePassErrorCode {
     where
         D: serde::Deserializer<'de>,
     {
-        let u = String::deserialize(deserializer)?
-            .parse::<u8>()
-            .unwrap_or(0u8);
+        // sent as string, but take numbers as well
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum StringOrNumber {
+            String(String),
+            Number(u64),
+        }
+        let u = match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(s) => s.parse::<u8>().unwrap_or(0u8),
+            StringOrNumber::Number(n) => u8::try_from(n).unwrap_or(0u8),
+        };
         Ok(KeePassErrorCode::from(u))
     }
 }
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
 #[derive(Clone, Debug)]
 pub struct KeePassBoolean(pub bool);
@@ -18,11 +19,20 @@ impl<'de> Deserialize<'de> for KeePassBoolean {
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?.to_lowercase();
-        match s.as_str() {
-            "true" => Ok(KeePassBoolean(true)),
-            "false" => Ok(KeePassBoolean(false)),
-            _ => Err(serde::de::Error::custom(format!("Unknown boolean {s}"))),
+        // usually "true" or "false", but take JSON booleans as well
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum StringOrBool {
+            String(String),
+            Bool(bool),
+        }
+        match StringOrBool::deserialize(deserializer)? {
+            StringOrBool::Bool(b) => Ok(KeePassBoolean(b)),
+            StringOrBool::String(s) => match s.to_lowercase().as_str() {
+                "true" => Ok(KeePassBoolean(true)),
+                "false" => Ok(KeePassBoolean(false)),
+                _ => Err(serde::de::Error::custom(format!("Unknown boolean {s}"))),
+            },
         }
     }
 }
@@ -44,32 +54,38 @@ macro_rules! define_action {
         #[derive(Clone, Debug, PartialEq, Eq)]
         pub enum KeePassAction {
             $($variant,)*
+            /// e.g. notifications introduced by newer KeePassXC versions
+            Unknown(String),
         }
 
         impl KeePassAction {
             pub fn to_readable(&self) -> String {
-                match *self {
+                match self {
                     $(Self::$variant => $readable.to_owned(),)*
+                    Self::Unknown(s) => s.clone(),
                 }
             }
-        }
 
-        impl ToString for KeePassAction {
-            fn to_string(&self) -> String {
-                match *self {
-                    $(Self::$variant => $string.to_owned(),)*
+            fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $string,)*
+                    Self::Unknown(s) => s,
                 }
             }
         }
 
+        impl fmt::Display for KeePassAction {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
         impl Serialize for KeePassAction {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                serializer.serialize_str(match *self {
-                    $(Self::$variant => $string,)*
-                })
+                serializer.serialize_str(self.as_str())
             }
         }
 
@@ -78,10 +94,10 @@ macro_rules! define_action {
             where
                 D: Deserializer<'de>,
             {
-                let s = String::deserialize(deserializer)?.to_lowercase();
-                Ok(match s.as_str() {
+                let s = String::deserialize(deserializer)?;
+                Ok(match s.to_lowercase().as_str() {
                     $($string => Self::$variant,)*
-                    _ => panic!("Unknown action: {}", s),
+                    _ => Self::Unknown(s),
                 })
             }
         }
@@ -110,4 +126,5 @@ define_action!([
     (DatabaseUnlocked, "database-unlocked", "database unlocked"),
     (CreateNewGroup, "create-new-group", "create new group"),
     (GetTotp, "get-totp", "get TOTP"),
+    (DeleteEntry, "delete-entry", "delete entry"),
 ]);
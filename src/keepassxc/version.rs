@@ -0,0 +1,158 @@
+use super::errors::UnsupportedFeatureError;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use anyhow::{anyhow, Result};
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+thread_local!(static SERVER_VERSION: Cell<Option<Version>> = const { Cell::new(None) });
+
+/// KeePassXC version as sent in the version field of responses, e.g. 2.7.6
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // ignore suffixes of development builds, e.g. 2.8.0-snapshot
+        let numbers = s.trim().split(['-', '+', ' ']).next().unwrap_or_default();
+        let mut parts = numbers.split('.').map(u32::from_str);
+        let mut next = |required: bool| match parts.next() {
+            Some(Ok(n)) => Ok(n),
+            None if !required => Ok(0),
+            _ => Err(anyhow!("Invalid KeePassXC version {}", s)),
+        };
+        Ok(Self::new(next(true)?, next(true)?, next(false)?))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Parts of the protocol which older KeePassXC versions lack
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    /// group of entries in get-logins responses
+    EntryGroups,
+    DatabaseGroups,
+    CreateNewGroup,
+    Totp,
+    /// "Only/Do not use this entry with HTTP Basic Auth" settings of entries
+    HttpAuthSettings,
+}
+
+impl Feature {
+    pub fn min_version(&self) -> Version {
+        match self {
+            Self::EntryGroups | Self::DatabaseGroups | Self::CreateNewGroup => {
+                Version::new(2, 6, 0)
+            }
+            Self::Totp => Version::new(2, 6, 1),
+            Self::HttpAuthSettings => Version::new(2, 7, 0),
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::EntryGroups => "Filtering entries by group",
+            Self::DatabaseGroups => "Listing groups",
+            Self::CreateNewGroup => "Creating groups",
+            Self::Totp => "Getting TOTP",
+            Self::HttpAuthSettings => "HTTP Basic Auth settings of entries",
+        })
+    }
+}
+
+/// Remembers the version of KeePassXC for the current session
+pub fn set_server_version(version: Option<&str>) {
+    let version = match version.map(Version::from_str) {
+        Some(Ok(version)) => {
+            info!("KeePassXC version: {}", version);
+            Some(version)
+        }
+        Some(Err(e)) => {
+            warn!("{}", e);
+            None
+        }
+        None => {
+            warn!("KeePassXC didn't tell its version");
+            None
+        }
+    };
+    SERVER_VERSION.with(|v| v.set(version));
+}
+
+pub fn server_version() -> Option<Version> {
+    SERVER_VERSION.with(Cell::get)
+}
+
+/// Features are assumed to be supported if the version is unknown, leaving it to KeePassXC to reject
+/// the request
+pub fn is_supported(feature: Feature) -> bool {
+    server_version().is_none_or(|version| version >= feature.min_version())
+}
+
+pub fn ensure_supported(feature: Feature) -> Result<()> {
+    match server_version() {
+        Some(version) if version < feature.min_version() => {
+            Err(UnsupportedFeatureError { feature, version }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_parse_version() {
+        assert_eq!(Version::from_str("2.7.6").unwrap(), Version::new(2, 7, 6));
+        assert_eq!(
+            Version::from_str("2.8.0-snapshot").unwrap(),
+            Version::new(2, 8, 0)
+        );
+        assert_eq!(Version::from_str("2.6").unwrap(), Version::new(2, 6, 0));
+        assert!(Version::from_str("").is_err());
+        assert!(Version::from_str("2").is_err());
+        assert!(Version::from_str("git-credential-keepassxc mock").is_err());
+        assert!(Version::new(2, 6, 1) > Version::new(2, 6, 0));
+        assert!(Version::new(2, 10, 0) > Version::new(2, 9, 9));
+    }
+
+    #[test]
+    fn test_01_feature_gates() {
+        set_server_version(Some("2.6.0"));
+        assert!(is_supported(Feature::EntryGroups));
+        assert!(!is_supported(Feature::Totp));
+        let e = ensure_supported(Feature::Totp).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Getting TOTP requires KeePassXC 2.6.1 or later, but 2.6.0 is running"
+        );
+        set_server_version(None);
+        assert!(is_supported(Feature::Totp));
+        assert!(ensure_supported(Feature::HttpAuthSettings).is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+//! Kubernetes client-go credential plugin, see
+//! https://kubernetes.io/docs/reference/access-authn-authz/authentication/#client-go-credential-plugins
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::git::GitCredentialMessage;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, start_session, verify_caller};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+
+const EXEC_INFO_ENVIRONMENT_VARIABLE: &str = "KUBERNETES_EXEC_INFO";
+const SUPPORTED_API_VERSIONS: &[&str] = &[
+    "client.authentication.k8s.io/v1",
+    "client.authentication.k8s.io/v1beta1",
+];
+const EXEC_CREDENTIAL_KIND: &str = "ExecCredential";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecInfo {
+    api_version: String,
+    #[serde(default)]
+    spec: ExecInfoSpec,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct ExecInfoSpec {
+    // only provided when provideClusterInfo is true in kubeconfig
+    #[serde(default)]
+    cluster: Option<ExecInfoCluster>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExecInfoCluster {
+    server: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecCredential {
+    api_version: String,
+    kind: &'static str,
+    status: ExecCredentialStatus,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecCredentialStatus {
+    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration_timestamp: Option<String>,
+}
+
+fn read_exec_info() -> Result<Option<ExecInfo>> {
+    let exec_info = match env::var(EXEC_INFO_ENVIRONMENT_VARIABLE) {
+        Ok(exec_info) => exec_info,
+        Err(_) => return Ok(None),
+    };
+    debug!("{}: {}", EXEC_INFO_ENVIRONMENT_VARIABLE, exec_info);
+    let exec_info: ExecInfo = serde_json::from_str(&exec_info)
+        .with_context(|| format!("Invalid {}", EXEC_INFO_ENVIRONMENT_VARIABLE))?;
+    if !SUPPORTED_API_VERSIONS.contains(&exec_info.api_version.as_str()) {
+        return Err(anyhow!(
+            "Unsupported apiVersion {}, expected one of {}",
+            exec_info.api_version,
+            SUPPORTED_API_VERSIONS.join(", ")
+        ));
+    }
+    Ok(Some(exec_info))
+}
+
+pub fn kubernetes<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubKubernetesArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let exec_info = read_exec_info()?;
+    let server = match (&args.server, &exec_info) {
+        (Some(server), _) => server.clone(),
+        (None, Some(ExecInfo { spec: ExecInfoSpec { cluster: Some(cluster) }, .. })) => {
+            cluster.server.clone()
+        }
+        _ => {
+            return Err(anyhow!(
+                "Cluster server URL is unknown, set provideClusterInfo to true in kubeconfig or use --server"
+            ))
+        }
+    };
+    let api_version = exec_info
+        .map(|exec_info| exec_info.api_version)
+        .unwrap_or_else(|| SUPPORTED_API_VERSIONS[0].to_owned());
+
+    let url = normalise_url(&server, config.get_url_policies());
+    let (client_id, _, _) = start_session()?;
+    let login_entry = get_first_login_for(
+        &config,
+        &client_id,
+        &url,
+        None,
+        &entry_filters,
+        unlock_options,
+    )?
+    .ok_or_else(|| anyhow!("No token found for cluster {}", server))?;
+
+    let mut attributes = GitCredentialMessage::default();
+    if let Some(ref string_fields) = login_entry.string_fields {
+        attributes.set_credential_attributes(string_fields);
+    }
+
+    let exec_credential = ExecCredential {
+        api_version,
+        kind: EXEC_CREDENTIAL_KIND,
+        status: ExecCredentialStatus {
+            token: login_entry.password,
+            expiration_timestamp: attributes.password_expiry_rfc3339(),
+        },
+    };
+    io::stdout().write_all(serde_json::to_string(&exec_credential)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_exec_info() {
+        let exec_info: ExecInfo = serde_json::from_str(
+            r#"{"apiVersion":"client.authentication.k8s.io/v1","kind":"ExecCredential","spec":{"interactive":false,"cluster":{"server":"https://127.0.0.1:6443","certificate-authority-data":"Zm9v"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(exec_info.api_version, "client.authentication.k8s.io/v1");
+        assert_eq!(
+            exec_info.spec.cluster.unwrap().server,
+            "https://127.0.0.1:6443"
+        );
+
+        let exec_info: ExecInfo = serde_json::from_str(
+            r#"{"apiVersion":"client.authentication.k8s.io/v1","kind":"ExecCredential","spec":{"interactive":true}}"#,
+        )
+        .unwrap();
+        assert!(exec_info.spec.cluster.is_none());
+    }
+
+    #[test]
+    fn test_01_exec_credential() {
+        let exec_credential = ExecCredential {
+            api_version: SUPPORTED_API_VERSIONS[0].to_owned(),
+            kind: EXEC_CREDENTIAL_KIND,
+            status: ExecCredentialStatus {
+                token: "foo".to_owned(),
+                expiration_timestamp: Some("2023-11-14T22:13:20Z".to_owned()),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&exec_credential).unwrap(),
+            r#"{"apiVersion":"client.authentication.k8s.io/v1","kind":"ExecCredential","status":{"token":"foo","expirationTimestamp":"2023-11-14T22:13:20Z"}}"#
+        );
+    }
+}
@@ -1,16 +1,28 @@
+mod agent;
+mod askpass;
+mod aws;
+mod cargo;
 mod cli;
 mod config;
+mod docker;
+mod exec;
 mod git;
+mod inject;
 mod keepassxc;
+mod kubernetes;
+mod pinentry;
+mod terraform;
 mod utils;
+mod watch;
 
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use cli::{EntryFilters, GetMode, HasEntryFilters, UnlockOptions};
-use config::{Caller, Config, Database};
+use config::{Caller, Config, Database, Quarantine};
 use crypto_box::{PublicKey, SecretKey};
+use git::format::OutputFormat;
 use git::GitCredentialMessage;
-use keepassxc::{errors::*, messages::*, Group};
+use keepassxc::{errors::*, messages::*, version::*, Group};
 use once_cell::sync::OnceCell;
 use slog::{Drain, Level, Logger};
 use std::env;
@@ -20,9 +32,10 @@ use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tabwriter::TabWriter;
 use utils::callers::CurrentCaller;
+use utils::url::{normalise_url, LookupOptions};
 use utils::*;
 
 use crate::cli::HasLocalEntryFilters;
@@ -33,26 +46,143 @@ fn exchange_keys<T: AsRef<str>>(client_id: T, session_pubkey: &PublicKey) -> Res
     // exchange public keys
     let cpr_req = ChangePublicKeysRequest::new(client_id.as_ref(), session_pubkey);
     let cpr_resp = cpr_req.send()?;
+    set_server_version(cpr_resp.version.as_deref());
     cpr_resp
         .get_public_key()
         .ok_or_else(|| anyhow!("Failed to retrieve host public key"))
 }
 
+/// Exchanges keys with KeePassXC, or returns the existing session if there is one, as the
+/// crypto_box can only be initialised once
 fn start_session() -> Result<(String, SecretKey, PublicKey)> {
-    // generate keys for encrypting current session
-    let session_seckey = generate_secret_key();
-    let session_pubkey = session_seckey.public_key();
+    thread_local!(static SESSION: once_cell::unsync::OnceCell<(String, SecretKey, PublicKey)> = const { once_cell::unsync::OnceCell::new() });
+    SESSION.with(|s| {
+        s.get_or_try_init(|| {
+            // generate keys for encrypting current session
+            let session_seckey = generate_secret_key();
+            let session_pubkey = session_seckey.public_key();
 
-    // temporary client id
-    let (_, client_id) = nacl_nonce();
+            // temporary client id
+            let (_, client_id) = nacl_nonce();
 
-    // exchange public keys
-    let host_pubkey = exchange_keys(&client_id, &session_pubkey)?;
+            // exchange public keys
+            let host_pubkey = exchange_keys(&client_id, &session_pubkey)?;
+
+            // initialise crypto_box
+            let _ = get_client_box(Some(&host_pubkey), Some(&session_seckey));
+
+            Ok((client_id, session_seckey, host_pubkey))
+        })
+        .cloned()
+    })
+}
+
+fn is_database_unlocked_notification(message: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(message)
+        .ok()
+        .and_then(|message| message.get("action")?.as_str().map(str::to_owned))
+        .is_some_and(|action| action == KeePassAction::DatabaseUnlocked.to_string())
+}
 
-    // initialise crypto_box
-    let _ = get_client_box(Some(&host_pubkey), Some(&session_seckey));
+/// Waits for KeePassXC to notify that a database has been unlocked, polling every interval as well
+/// as older versions don't send the notification and the agent doesn't relay it. Returns false if
+/// the deadline has passed.
+fn wait_for_unlock(
+    client_id: &str,
+    db_id: &str,
+    unlock_options: &UnlockOptions,
+    deadline: Option<Instant>,
+) -> Result<bool> {
+    let listen = !is_connected_to_agent()?;
+    wait_for_unlock_with(
+        db_id,
+        unlock_options,
+        deadline,
+        || GetDatabaseHashRequest::new().send(client_id, false).is_ok(),
+        |until| {
+            if listen {
+                while Instant::now() < until {
+                    if receive_messages_until(until)?
+                        .iter()
+                        .any(|message| is_database_unlocked_notification(message))
+                    {
+                        return Ok(true);
+                    }
+                }
+            } else {
+                thread::sleep(until.saturating_duration_since(Instant::now()));
+            }
+            Ok(false)
+        },
+    )
+}
 
-    Ok((client_id, session_seckey, host_pubkey))
+/// Polls with is_unlocked every interval, and in between waits with wait_for_notification, which
+/// returns true once a database-unlocked notification arrives before the given instant. Polling is
+/// the fallback for KeePassXC versions which don't send the notification.
+fn wait_for_unlock_with<F, W>(
+    db_id: &str,
+    unlock_options: &UnlockOptions,
+    deadline: Option<Instant>,
+    mut is_unlocked: F,
+    mut wait_for_notification: W,
+) -> Result<bool>
+where
+    F: FnMut() -> bool,
+    W: FnMut(Instant) -> Result<bool>,
+{
+    let start = Instant::now();
+    match deadline {
+        Some(deadline) => {
+            warn!(
+                "Database {} is locked, waiting up to {:.1}s for it to be unlocked",
+                db_id,
+                deadline.saturating_duration_since(start).as_secs_f64()
+            );
+        }
+        None => {
+            warn!(
+                "Database {} is locked, waiting for it to be unlocked",
+                db_id
+            );
+        }
+    }
+    // the database may have been unlocked before listening started, and there won't be a
+    // notification then
+    if is_unlocked() {
+        info!("Database {} has been unlocked", db_id);
+        return Ok(true);
+    }
+    loop {
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            warn!(
+                "Database {} is still locked after waiting {:.1}s",
+                db_id,
+                start.elapsed().as_secs_f64()
+            );
+            return Ok(false);
+        }
+        let next_poll = match deadline {
+            Some(deadline) => deadline.min(now + unlock_options.interval()),
+            None => now + unlock_options.interval(),
+        };
+        if wait_for_notification(next_poll)? {
+            info!(
+                "Received database-unlocked notification after waiting {:.1}s",
+                start.elapsed().as_secs_f64()
+            );
+            return Ok(true);
+        }
+        if is_unlocked() {
+            info!(
+                "Database {} is unlocked after waiting {:.1}s",
+                db_id,
+                start.elapsed().as_secs_f64()
+            );
+            return Ok(true);
+        }
+    }
 }
 
 fn associated_databases<T: AsRef<str>>(
@@ -60,11 +190,11 @@ fn associated_databases<T: AsRef<str>>(
     client_id: T,
     unlock_options: &Option<UnlockOptions>,
 ) -> Result<Vec<Database>> {
+    let mut unlock_deadline = None;
     let databases: Vec<_> = config
         .get_databases()?
         .iter()
         .filter(|db| {
-            let mut remain_retries = unlock_options.as_ref().map_or_else(|| 0, |v| v.max_retries);
             let mut success = false;
             loop {
                 let taso_req = TestAssociateRequest::new(db.id.as_str(), db.pkey.as_str());
@@ -81,7 +211,7 @@ fn associated_databases<T: AsRef<str>>(
                     }
                 };
                 if let Ok((ref taso_resp, _)) = taso_resp {
-                    success = taso_resp.success.as_ref().map_or(false, |s| *s.as_ref());
+                    success = taso_resp.success.as_ref().is_some_and(|s| *s.as_ref());
                 }
                 if taso_resp.is_err() || !success {
                     warn!(
@@ -89,33 +219,23 @@ fn associated_databases<T: AsRef<str>>(
                         db.id
                     );
                 }
-                if success || !database_locked || unlock_options.is_none() {
-                    break;
-                }
-                // loop get-databasehash until unlocked
-                while remain_retries > 0 || unlock_options.as_ref().unwrap().max_retries == 0 {
-                    warn!(
-                        "Database {} is locked, gonna retry in {}ms (Remaining: {})",
-                        db.id,
-                        unlock_options.as_ref().unwrap().interval,
-                        remain_retries
-                    );
-                    thread::sleep(Duration::from_millis(
-                        unlock_options.as_ref().unwrap().interval,
-                    ));
-
-                    let gh_req = GetDatabaseHashRequest::new();
-                    if gh_req.send(client_id.as_ref(), false).is_ok() {
-                        info!("Database {} is unlocked", db.id);
+                let unlock_options = match unlock_options {
+                    Some(unlock_options) if !success && database_locked => unlock_options,
+                    _ => break,
+                };
+                // shared by all databases
+                let deadline = *unlock_deadline
+                    .get_or_insert_with(|| unlock_options.timeout().map(|t| Instant::now() + t));
+                match wait_for_unlock(client_id.as_ref(), &db.id, unlock_options, deadline) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        warn!(
+                            "Failed to wait for database {} to be unlocked: {}",
+                            db.id, e
+                        );
                         break;
                     }
-                    if unlock_options.as_ref().unwrap().max_retries != 0 {
-                        remain_retries -= 1;
-                    }
-                }
-                // still not unlocked, break
-                if remain_retries == 0 && unlock_options.as_ref().unwrap().max_retries != 0 {
-                    break;
                 }
             }
             success
@@ -190,6 +310,7 @@ fn configure<T: AsRef<Path>>(config_path: T, args: &cli::SubConfigureArgs) -> Re
     if args.group.is_empty() {
         return Err(anyhow!("Group name must not be empty"));
     }
+    ensure_supported(Feature::CreateNewGroup)?;
     let cng_req = CreateNewGroupRequest::new(&args.group);
     let (cng_resp, _) = cng_req.send(&client_id, false)?;
     let group = Group::new(cng_resp.name, cng_resp.uuid);
@@ -258,6 +379,9 @@ fn encrypt<T: AsRef<Path>>(config_path: T, args: &cli::SubEncryptArgs) -> Result
 
     let count_databases_encrypted = config_file.encrypt_databases()?;
     let count_callers_encrypted = config_file.encrypt_callers()?;
+    // encrypt quarantine profiles as well now that database profiles are encrypted
+    let quarantines = config_file.get_quarantines()?;
+    config_file.set_quarantines(quarantines)?;
     info!(
         "{} database profile(s) encrypted",
         count_databases_encrypted
@@ -285,8 +409,10 @@ fn decrypt<T: AsRef<Path>>(config_path: T) -> Result<()> {
     );
     info!("{} caller profile(s) to decrypt", count_callers_to_decrypt);
 
+    let quarantines = config_file.get_quarantines()?;
     config_file.decrypt_databases()?;
     config_file.decrypt_callers()?;
+    config_file.set_quarantines(quarantines)?;
     if config_file.count_encrypted_databases() == 0 && config_file.count_encrypted_callers() == 0 {
         config_file.clear_encryptions();
     }
@@ -358,10 +484,13 @@ fn caller<T: AsRef<Path>>(config_path: T, args: &cli::SubCallerArgs) -> Result<(
     }
 }
 
+/// Whether callers have to be verified, i.e. there are caller profiles or strict-caller applies
+fn requires_caller_verification(config: &Config) -> bool {
+    config.count_callers() > 0 || (cfg!(feature = "strict-caller") && config.count_databases() > 0)
+}
+
 fn verify_caller(config: &Config) -> Result<Option<CurrentCaller>> {
-    if config.count_callers() == 0
-        && (cfg!(not(feature = "strict-caller")) || config.count_databases() == 0)
-    {
+    if !requires_caller_verification(config) {
         info!(
             "Caller verification skipped as no caller profiles defined and strict-caller disabled"
         );
@@ -398,8 +527,102 @@ fn verify_caller(config: &Config) -> Result<Option<CurrentCaller>> {
     }
 }
 
+/// Adds the entries of another raw get-logins response which aren't in the merged one yet
+fn merge_raw_logins(merged: &mut serde_json::Value, raw: serde_json::Value) {
+    let new_entries = match raw.get("entries").and_then(|entries| entries.as_array()) {
+        Some(entries) => entries.clone(),
+        None => return,
+    };
+    let entries = match merged
+        .as_object_mut()
+        .map(|merged| {
+            merged
+                .entry("entries")
+                .or_insert_with(|| serde_json::json!([]))
+        })
+        .and_then(|entries| entries.as_array_mut())
+    {
+        Some(entries) => entries,
+        None => return,
+    };
+    for entry in new_entries {
+        if !entries.iter().any(|e| e.get("uuid") == entry.get("uuid")) {
+            entries.push(entry);
+        }
+    }
+    let count = entries.len();
+    // sent as a string by some versions
+    match merged.get_mut("count") {
+        Some(c @ serde_json::Value::String(_)) => *c = count.to_string().into(),
+        Some(c) => *c = count.into(),
+        None => {}
+    }
+}
+
+/// Sends get-logins for each of the URLs, keeping the entries in the order they are found, i.e. by
+/// URL first and then as KeePassXC returns them. KeePassXC matches entries by host and doesn't send
+/// their URLs, so entries can't be ranked by how much of the path they match. The raw response is
+/// the first one with the entries of the others added.
+fn request_logins(
+    client_id: &str,
+    urls: &[String],
+    lookup_options: &LookupOptions,
+    id_key_pairs: &[(&str, &str)],
+) -> Result<(Vec<LoginEntry>, String)> {
+    let mut login_entries: Vec<LoginEntry> = Vec::new();
+    let mut gl_resp_raw: Option<serde_json::Value> = None;
+    for url in urls {
+        // ask KeePassXC for logins
+        let gl_req = GetLoginsRequest::new(
+            url.as_str(),
+            lookup_options.submit_url.as_deref(),
+            lookup_options.http_auth.then_some(KeePassBoolean(true)),
+            id_key_pairs,
+        );
+        let gl_resp = match gl_req.send(client_id, false) {
+            Ok((gl_resp, raw)) => {
+                let raw = serde_json::from_str(&raw)?;
+                match gl_resp_raw {
+                    Some(ref mut merged) => merge_raw_logins(merged, raw),
+                    None => gl_resp_raw = Some(raw),
+                }
+                gl_resp
+            }
+            Err(e) if urls.len() > 1 => match e.downcast_ref::<KeePassError>() {
+                Some(keepass_error) if keepass_error.is_no_logins_found() => {
+                    info!("No logins found for {}", url);
+                    continue;
+                }
+                _ => return Err(e),
+            },
+            Err(e) => return Err(e),
+        };
+        let num_entries = login_entries.len();
+        for entry in gl_resp.entries {
+            if !login_entries.iter().any(|e| e.uuid == entry.uuid) {
+                login_entries.push(entry);
+            }
+        }
+        if urls.len() > 1 {
+            info!(
+                "{} new login(s) found for {}",
+                login_entries.len() - num_entries,
+                url
+            );
+        }
+    }
+
+    let gl_resp_raw = match gl_resp_raw {
+        Some(raw) => serde_json::to_string(&raw)?,
+        None => String::new(),
+    };
+    Ok((login_entries, gl_resp_raw))
+}
+
 /// Returns all entries from KeePassXC except for expired ones (which are not returned by KeePassXC
 /// actually, but better to be safe than sorry)
+///
+/// With path prefixes matching, entries found for longer URL prefixes come first
 fn get_logins_for<T: AsRef<str>>(
     config: &Config,
     client_id: T,
@@ -407,18 +630,53 @@ fn get_logins_for<T: AsRef<str>>(
     filters: &EntryFilters,
     unlock_options: &Option<UnlockOptions>,
 ) -> Result<(Vec<LoginEntry>, String)> {
+    if !filters.groups.is_empty() || filters.git_groups {
+        ensure_supported(Feature::EntryGroups)?;
+    }
     let databases = associated_databases(config, client_id.as_ref(), unlock_options)?;
     let id_key_pairs: Vec<_> = databases
         .iter()
         .map(|d| (d.id.as_str(), d.pkey.as_str()))
         .collect();
 
-    // ask KeePassXC for logins
-    let gl_req = GetLoginsRequest::new(url.as_ref(), None, None, &id_key_pairs[..]);
-    let (gl_resp, gl_resp_raw) = gl_req.send(client_id.as_ref(), false)?;
+    let urls = if filters.path_prefixes {
+        git::url_path_prefixes(url.as_ref())
+    } else {
+        vec![url.as_ref().to_owned()]
+    };
+    let mut lookup_options = LookupOptions::for_url(url.as_ref(), config.get_url_policies());
+    lookup_options.http_auth |= filters.http_auth;
+    if filters.submit_url.is_some() {
+        lookup_options.submit_url.clone_from(&filters.submit_url);
+    }
+    let mut result = request_logins(client_id.as_ref(), &urls, &lookup_options, &id_key_pairs);
+    let found = match &result {
+        Ok((login_entries, _)) => !login_entries.is_empty(),
+        Err(e) => !e
+            .downcast_ref::<KeePassError>()
+            .is_some_and(KeePassError::is_no_logins_found),
+    };
+    if !found
+        && !lookup_options.http_auth
+        && lookup_options.http_auth_fallback
+        && is_supported(Feature::HttpAuthSettings)
+    {
+        // KeePassXC only returns entries set to "Only HTTP Basic Auth" when httpAuth is sent
+        info!("No logins found, retrying with httpAuth for entries only used for HTTP Basic Auth");
+        lookup_options.http_auth = true;
+        match request_logins(client_id.as_ref(), &urls, &lookup_options, &id_key_pairs) {
+            Ok(http_auth_result) if !http_auth_result.0.is_empty() => {
+                result = Ok(http_auth_result);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to look up logins with httpAuth: {}", e);
+            }
+        }
+    }
+    let (login_entries, gl_resp_raw) = result?;
 
-    let mut login_entries: Vec<_> = gl_resp
-        .entries
+    let mut login_entries: Vec<_> = login_entries
         .into_iter()
         .filter(|e| e.expired.is_none() || !e.expired.as_ref().unwrap().0)
         .collect();
@@ -450,6 +708,7 @@ fn get_logins_for<T: AsRef<str>>(
 }
 
 fn get_totp_for<T: AsRef<str>>(client_id: T, uuid: T) -> Result<GetTotpResponse> {
+    ensure_supported(Feature::Totp)?;
     let gt_req = GetTotpRequest::new(uuid.as_ref());
     let (mut gt_resp, _) = gt_req.send(client_id.as_ref(), false)?;
     gt_resp.uuid = Some(uuid.as_ref().to_owned());
@@ -461,6 +720,30 @@ fn get_totp_for<T: AsRef<str>>(client_id: T, uuid: T) -> Result<GetTotpResponse>
     }
 }
 
+/// Returns a field of the login by name, i.e. username, password, totp, name, uuid, or an advanced
+/// string field with or without the KPH: prefix
+fn get_login_field_for<T: AsRef<str>>(
+    client_id: T,
+    login_entry: &LoginEntry,
+    field: T,
+) -> Result<String> {
+    let field = field.as_ref();
+    match field {
+        "username" | "login" => Ok(login_entry.login.clone()),
+        "password" => Ok(login_entry.password.clone()),
+        "totp" => Ok(get_totp_for(client_id.as_ref(), login_entry.uuid.as_str())?.totp),
+        "name" | "title" => Ok(login_entry.name.clone()),
+        "uuid" => Ok(login_entry.uuid.clone()),
+        _ => login_entry
+            .get_string_field(field)
+            .or_else(|| login_entry.get_string_field(format!("KPH: {}", field)))
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("Field {} not found in {}", field, login_entry.name)),
+    }
+}
+
+const KPH_GIT_AUTHTYPE_FIELD: &str = "KPH: git-authtype";
+
 fn filter_kph(login_entry: &LoginEntry) -> bool {
     if let Some(ref string_fields) = login_entry.string_fields {
         let kph_false_fields = string_fields.iter().find(|m| {
@@ -476,6 +759,16 @@ fn filter_kph(login_entry: &LoginEntry) -> bool {
     }
 }
 
+fn filter_password_expiry(login_entry: &LoginEntry) -> bool {
+    if let Some(ref string_fields) = login_entry.string_fields {
+        let mut attributes = GitCredentialMessage::default();
+        attributes.set_credential_attributes(string_fields);
+        !attributes.is_password_expired()
+    } else {
+        true
+    }
+}
+
 fn filter_group(
     login_entry: &LoginEntry,
     groups: &[String],
@@ -490,12 +783,84 @@ fn filter_group(
         groups.contains(login_entry_group) || database_groups.contains(&login_entry_group)
     } else {
         if !groups.is_empty() || git_groups {
-            warn!("Group filter(s) provided but no group info from KeePassXC (using KeePassXC < 2.6.0?)");
+            warn!("Group filter(s) provided but no group info from KeePassXC");
         }
         true
     }
 }
 
+/// Returns the first usable login for the URL (and of the username if given), or None if KeePassXC
+/// has found nothing
+fn get_first_login_for<T: AsRef<str>>(
+    config: &Config,
+    client_id: T,
+    url: T,
+    username: Option<&str>,
+    filters: &EntryFilters,
+    unlock_options: &Option<UnlockOptions>,
+) -> Result<Option<LoginEntry>> {
+    let mut login_entries = match get_logins_for(
+        config,
+        client_id.as_ref(),
+        url.as_ref(),
+        filters,
+        unlock_options,
+    ) {
+        Ok((login_entries, _)) => login_entries,
+        Err(e) => match e.downcast_ref::<KeePassError>() {
+            Some(keepass_error) if keepass_error.is_no_logins_found() => Vec::new(),
+            _ => return Err(e),
+        },
+    };
+    filter_logins(config, url.as_ref(), &mut login_entries)?;
+    if let Some(username) = username {
+        login_entries.retain(|login_entry| login_entry.login == username);
+        info!(
+            "{} login(s) left after filtering by username",
+            login_entries.len()
+        );
+    }
+    if login_entries.len() > 1 {
+        warn!("More than 1 matching logins found, only the first one will be returned");
+    }
+    Ok(login_entries.into_iter().next())
+}
+
+/// Drops logins of which password_expiry_utc is in the past or which have been rejected by Git
+fn filter_logins<T: AsRef<str>>(
+    config: &Config,
+    url: T,
+    login_entries: &mut Vec<LoginEntry>,
+) -> Result<()> {
+    let num_entries = login_entries.len();
+    login_entries.retain(filter_password_expiry);
+    let num_filtered = num_entries - login_entries.len();
+    if num_filtered > 0 {
+        info!(
+            "{} login(s) were filtered out due to expired password_expiry_utc",
+            num_filtered
+        );
+    }
+
+    if let (true, Some(key)) = (config.count_quarantines() > 0, config.get_quarantine_key()?) {
+        let quarantines = config.get_quarantines()?;
+        let num_entries = login_entries.len();
+        login_entries.retain(|login_entry| {
+            !quarantines
+                .iter()
+                .any(|q| q.matches(url.as_ref(), &login_entry.uuid, &login_entry.password, &key))
+        });
+        let num_filtered = num_entries - login_entries.len();
+        if num_filtered > 0 {
+            info!(
+                "{} login(s) were filtered out as they had been rejected by Git",
+                num_filtered
+            );
+        }
+    }
+    Ok(())
+}
+
 fn get_logins<T, A>(
     config_path: T,
     unlock_options: &Option<UnlockOptions>,
@@ -507,10 +872,26 @@ where
     A: cli::GetOperation,
 {
     let config = Config::read_from(config_path.as_ref())?;
-    let _current_caller = verify_caller(&config)?;
+    let _current_caller = match verify_caller(&config) {
+        Ok(current_caller) => current_caller,
+        Err(e) => {
+            if args.get_mode() != GetMode::TotpOnly
+                && !args.raw()
+                && args.output_format() == OutputFormat::Git
+            {
+                // tell Git to stop trying other helpers and prompting
+                let git_resp = GitCredentialMessage {
+                    quit: Some("1".to_owned()),
+                    ..Default::default()
+                };
+                io::stdout().write_all(git_resp.to_string().as_bytes())?;
+            }
+            return Err(e);
+        }
+    };
     // read credential request
     let git_req = GitCredentialMessage::from_stdin()?;
-    let url = git_req.get_url()?;
+    let url = normalise_url(git_req.get_url()?, config.get_url_policies());
 
     #[cfg(feature = "notification")]
     {
@@ -571,11 +952,12 @@ where
         return Ok(());
     }
 
+    filter_logins(&config, &url, &mut login_entries)?;
+
     if login_entries.is_empty() {
         return Err(anyhow!("No matching logins found"));
     }
-    if login_entries.len() > 1 && git_req.username.is_some() {
-        let username = git_req.username.as_ref().unwrap();
+    if let (true, Some(username)) = (login_entries.len() > 1, &git_req.username) {
         let login_entries_name_matches: Vec<_> = login_entries
             .iter()
             .filter(|entry| entry.login == *username)
@@ -594,7 +976,32 @@ where
     }
 
     let login = login_entries.first().unwrap();
+    let authtype = login
+        .get_string_field(KPH_GIT_AUTHTYPE_FIELD)
+        .filter(|authtype| !authtype.is_empty())
+        .map(str::to_owned);
+    let use_authtype = authtype.is_some() && git_req.has_capability("authtype");
+    if authtype.is_some() && !use_authtype {
+        warn!("Entry has {} set but Git doesn't support authtype capability, gonna return username and password instead", KPH_GIT_AUTHTYPE_FIELD);
+    }
+    let www_auth_schemes = git_req.www_auth_schemes();
+    let scheme = if use_authtype {
+        authtype.as_deref().unwrap().to_ascii_lowercase()
+    } else {
+        "basic".to_owned()
+    };
+    if !www_auth_schemes.is_empty() && !www_auth_schemes.contains(&scheme) {
+        warn!(
+            "Server doesn't accept {} authentication (challenges: {}), the returned credential may be rejected",
+            scheme,
+            www_auth_schemes.join(", ")
+        );
+    }
+
     let mut git_resp = git_req;
+    // challenges are not part of the credential
+    git_resp.wwwauth.clear();
+    git_resp.negotiate_capabilities();
 
     // entry found handle TOTP now
     match args.get_mode() {
@@ -612,8 +1019,16 @@ where
     }
 
     if args.get_mode() != GetMode::TotpOnly {
-        git_resp.username = Some(login.login.clone());
-        git_resp.password = Some(login.password.clone());
+        if use_authtype {
+            git_resp.authtype = authtype;
+            git_resp.credential = Some(login.password.clone());
+        } else {
+            git_resp.username = Some(login.login.clone());
+            git_resp.password = Some(login.password.clone());
+        }
+        if let Some(ref login_entry_fields) = login.string_fields {
+            git_resp.set_credential_attributes(login_entry_fields);
+        }
     }
 
     if args.advanced_fields() {
@@ -624,11 +1039,7 @@ where
         }
     }
 
-    if args.json() {
-        io::stdout().write_all(serde_json::to_string(&git_resp)?.as_bytes())?;
-    } else {
-        io::stdout().write_all(git_resp.to_string().as_bytes())?;
-    }
+    io::stdout().write_all(git_resp.format(&args.output_format())?.as_bytes())?;
 
     Ok(())
 }
@@ -643,10 +1054,37 @@ fn store_login<T: AsRef<Path>>(
     verify_caller(&config)?;
     // read credential request
     let git_req = GitCredentialMessage::from_stdin()?;
-    let url = git_req.get_url()?;
+    store_login_for(
+        config,
+        config_path,
+        unlock_options,
+        &entry_filters,
+        args.create_in.as_ref(),
+        git_req,
+    )
+}
+
+/// Updates the existing entry of the same username or creates a new one, the caller must have been
+/// verified
+fn store_login_for<T: AsRef<Path>>(
+    mut config: Config,
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: &EntryFilters,
+    create_in: Option<&String>,
+    git_req: GitCredentialMessage,
+) -> Result<()> {
+    let url = normalise_url(git_req.get_url()?, config.get_url_policies());
     // start session
     let (client_id, _, _) = start_session()?;
 
+    if let (Some(authtype), None) = (&git_req.authtype, &git_req.password) {
+        info!(
+            "Storing {} credential is not supported, ignoring request",
+            authtype
+        );
+        return Ok(());
+    }
     if git_req.username.is_none() {
         return Err(anyhow!("Username is missing"));
     }
@@ -654,7 +1092,7 @@ fn store_login<T: AsRef<Path>>(
         return Err(anyhow!("Password is missing"));
     }
 
-    let login_entries = get_logins_for(&config, &client_id, &url, &entry_filters, unlock_options)
+    let login_entries = get_logins_for(&config, &client_id, &url, entry_filters, unlock_options)
         .and_then(|(entries, _)| {
             let username = git_req.username.as_ref().unwrap();
             let entries: Vec<_> = entries
@@ -673,7 +1111,7 @@ fn store_login<T: AsRef<Path>>(
             }
         });
 
-    let sl_req = if let Ok(login_entries) = login_entries {
+    let (sl_req, existing_uuid) = if let Ok(login_entries) = login_entries {
         if login_entries.len() == 1 {
             warn!("Existing login found, gonna update the entry");
         } else {
@@ -687,19 +1125,22 @@ fn store_login<T: AsRef<Path>>(
             // KeePassXC treats this as error, and Git sometimes does this as the operation should
             // be idempotent
             info!("No changes detected, ignoring request");
-            return Ok(());
+            warn_unsaved_credential_attributes(&git_req, Some(login_entry));
+            return release_quarantines(&mut config, config_path, &login_entry.uuid);
         }
 
         let databases = config.get_databases()?;
         if databases.len() > 1 {
             // how do I know which database it's from?
-            error!(
-                "Trying to update an existing login when multiple databases are configured, this is not implemented yet"
-            );
-            unimplemented!();
+            return Err(anyhow!(
+                "Updating an existing login is not supported when multiple databases are configured"
+            ));
         }
-        let database = databases.first().unwrap();
-        SetLoginRequest::new(
+        let database = databases
+            .first()
+            .ok_or_else(|| anyhow!("No databases configured"))?;
+        warn_unsaved_credential_attributes(&git_req, Some(login_entry));
+        let sl_req = SetLoginRequest::new(
             &url,
             &url,
             &database.id,
@@ -708,7 +1149,8 @@ fn store_login<T: AsRef<Path>>(
             Some(&database.group),
             Some(&database.group_uuid), // KeePassXC won't move the existing entry though
             Some(&login_entry.uuid),
-        )
+        );
+        (sl_req, Some(login_entry.uuid.clone()))
     } else {
         info!("No existing logins found, gonna create a new one");
         let databases = config.get_databases()?;
@@ -717,8 +1159,11 @@ fn store_login<T: AsRef<Path>>(
                 "More than 1 databases configured, gonna save the new login in the first database"
             );
         }
-        let database = databases.first().unwrap();
-        let (group, group_uuid) = if let Some(ref group) = args.create_in {
+        let database = databases
+            .first()
+            .ok_or_else(|| anyhow!("No databases configured"))?;
+        let (group, group_uuid) = if let Some(group) = create_in {
+            ensure_supported(Feature::DatabaseGroups)?;
             let gg_req = GetDatabaseGroupsRequest::new();
             let (gg_resp, _) = gg_req.send(&client_id, false)?;
             let group_uuid = gg_resp
@@ -732,7 +1177,8 @@ fn store_login<T: AsRef<Path>>(
         } else {
             (database.group.clone(), database.group_uuid.clone())
         };
-        SetLoginRequest::new(
+        warn_unsaved_credential_attributes(&git_req, None);
+        let sl_req = SetLoginRequest::new(
             &url,
             &url,
             &database.id,
@@ -741,20 +1187,227 @@ fn store_login<T: AsRef<Path>>(
             Some(&group),
             Some(&group_uuid),
             None,
-        )
+        );
+        (sl_req, None)
     };
     let (sl_resp, _) = sl_req.send(&client_id, false)?;
 
-    sl_resp.check(&sl_req.get_action())
+    sl_resp.check(&sl_req.get_action())?;
+    if let Some(uuid) = existing_uuid {
+        release_quarantines(&mut config, config_path, &uuid)?;
+    }
+    Ok(())
 }
 
-fn erase_login() -> Result<()> {
-    // Don't treat this as error as when server rejects a login Git may try to erase it. This is
-    // not desirable since sometimes it's merely a configuration issue, e.g. a lot of Git servers
-    // reject logins over HTTP(S) when SSH keys have been uploaded
-    error!("KeePassXC doesn't allow erasing logins via socket at the time of writing");
-    let _ = GitCredentialMessage::from_stdin()?;
-    Ok(())
+/// Clears quarantine of an entry once Git has asked to store it again
+fn release_quarantines<T: AsRef<Path>>(
+    config: &mut Config,
+    config_path: T,
+    uuid: &str,
+) -> Result<()> {
+    if config.count_quarantines() == 0 {
+        return Ok(());
+    }
+    let mut quarantines = config.get_quarantines()?;
+    let num_quarantines = quarantines.len();
+    quarantines.retain(|q| q.uuid != uuid);
+    if quarantines.len() == num_quarantines {
+        return Ok(());
+    }
+    info!(
+        "Releasing {} quarantined login(s) of entry {}",
+        num_quarantines - quarantines.len(),
+        uuid
+    );
+    config.set_quarantines(quarantines)?;
+    config.write_to(config_path)
+}
+
+/// KeePassXC doesn't allow setting advanced fields via socket, so credential attributes can only
+/// be kept if the user adds them to the entry manually
+fn warn_unsaved_credential_attributes(
+    git_req: &GitCredentialMessage,
+    login_entry: Option<&LoginEntry>,
+) {
+    let mut existing = GitCredentialMessage::default();
+    if let Some(string_fields) = login_entry.and_then(|e| e.string_fields.as_ref()) {
+        existing.set_credential_attributes(string_fields);
+    }
+    if git_req.password_expiry_utc.is_some()
+        && git_req.password_expiry_utc != existing.password_expiry_utc
+    {
+        warn!("KeePassXC doesn't allow setting advanced fields via socket, add 'KPH: password_expiry_utc' to the entry manually to keep it");
+    }
+    if git_req.oauth_refresh_token.is_some()
+        && git_req.oauth_refresh_token != existing.oauth_refresh_token
+    {
+        warn!("KeePassXC doesn't allow setting advanced fields via socket, add 'KPH: oauth_refresh_token' to the entry manually to keep it");
+    }
+}
+
+fn erase_login<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubEraseArgs,
+) -> Result<()> {
+    // By default rejected logins are quarantined locally so that Git won't receive them again.
+    // Deleting them is not desirable in most cases since sometimes it's merely a configuration
+    // issue, e.g. a lot of Git servers reject logins over HTTP(S) when SSH keys have been uploaded.
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+    // read credential request
+    let git_req = GitCredentialMessage::from_stdin()?;
+    erase_login_for(
+        config,
+        config_path,
+        unlock_options,
+        &entry_filters,
+        args,
+        git_req,
+    )
+}
+
+/// Deletes or quarantines the logins matching the rejected credential, the caller must have been
+/// verified
+fn erase_login_for<T: AsRef<Path>>(
+    mut config: Config,
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: &EntryFilters,
+    args: &cli::SubEraseArgs,
+    git_req: GitCredentialMessage,
+) -> Result<()> {
+    let url = normalise_url(git_req.get_url()?, config.get_url_policies());
+    // start session
+    let (client_id, _, _) = start_session()?;
+
+    let (login_entries, _) =
+        get_logins_for(&config, &client_id, &url, entry_filters, unlock_options)?;
+    // Git sends the rejected credential
+    let rejected_password = git_req.password.as_ref().or(git_req.credential.as_ref());
+    if git_req.username.is_none() && rejected_password.is_none() {
+        warn!(
+            "Neither username nor password of the rejected credential is given, nothing to erase"
+        );
+        return Ok(());
+    }
+    let mut rejected_entries: Vec<_> = login_entries
+        .iter()
+        .filter(|entry| {
+            git_req.username.as_ref().is_none_or(|u| entry.login == *u)
+                && rejected_password.is_none_or(|p| entry.password == *p)
+        })
+        .collect();
+    if rejected_entries.is_empty() {
+        info!("No matching logins found, nothing to erase");
+        return Ok(());
+    }
+
+    if args.dry_run {
+        for entry in rejected_entries {
+            println!("{}", entry.uuid);
+        }
+        return Ok(());
+    }
+
+    if args.delete {
+        if rejected_password.is_none() {
+            warn!("Refusing to delete entries as Git didn't send the rejected password, gonna quarantine them instead");
+        } else {
+            let mut undeleted_entries = Vec::new();
+            for entry in rejected_entries {
+                match delete_entry(&client_id, &entry.uuid) {
+                    Ok(_) => {
+                        warn!("Deleted login {} ({}) for {}", entry.name, entry.uuid, url);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to delete login {} ({}), gonna quarantine it instead. Caused by: {}",
+                            entry.name, entry.uuid, e
+                        );
+                        undeleted_entries.push(entry);
+                    }
+                }
+            }
+            rejected_entries = undeleted_entries;
+        }
+    }
+    if rejected_entries.is_empty() {
+        return Ok(());
+    }
+
+    let key = config.get_or_create_quarantine_key()?;
+    let mut quarantines = config.get_quarantines()?;
+    for entry in rejected_entries {
+        if quarantines
+            .iter()
+            .any(|q| q.matches(&url, &entry.uuid, &entry.password, &key))
+        {
+            continue;
+        }
+        warn!(
+            "Quarantining login {} ({}) for {}",
+            entry.name, entry.uuid, url
+        );
+        quarantines.push(Quarantine::new(&url, &entry.uuid, &entry.password, &key));
+    }
+    config.set_quarantines(quarantines)?;
+    config.write_to(config_path)
+}
+
+fn delete_entry<T: AsRef<str>>(client_id: T, uuid: T) -> Result<()> {
+    let de_req = DeleteEntryRequest::new(uuid.as_ref());
+    match de_req.send(client_id.as_ref(), false) {
+        Ok((de_resp, _)) => de_resp.check(&de_req.get_action()),
+        Err(e) => match e.downcast_ref::<KeePassError>() {
+            Some(keepass_error) if keepass_error.is_action_unsupported() => Err(anyhow!(
+                "KeePassXC doesn't support deleting entries via socket"
+            )),
+            _ => Err(e),
+        },
+    }
+}
+
+fn quarantine<T: AsRef<Path>>(config_path: T, args: &cli::SubQuarantineArgs) -> Result<()> {
+    let mut config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    match &args.command {
+        cli::QuarantineSubcommands::List(list_args) => {
+            let quarantines = config.get_quarantines()?;
+            if list_args.json {
+                io::stdout().write_all(serde_json::to_string(&quarantines)?.as_bytes())?;
+            } else {
+                let mut tw = TabWriter::new(io::stdout());
+                tw.write_all("URL\tUUID\tFingerprint\tSince\n".as_bytes())?;
+                tw.write_all("--\t--\t--\t--\n".as_bytes())?;
+                for quarantine in quarantines {
+                    tw.write_fmt(format_args!(
+                        "{}\t{}\t{}\t{}\n",
+                        quarantine.url, quarantine.uuid, quarantine.fingerprint, quarantine.since
+                    ))?;
+                }
+                tw.flush()?;
+            }
+            Ok(())
+        }
+        cli::QuarantineSubcommands::Clear(clear_args) => {
+            let mut quarantines = config.get_quarantines()?;
+            let num_quarantines = quarantines.len();
+            quarantines.retain(|q| {
+                let url_matches = clear_args.url.as_ref().is_none_or(|u| q.url == *u);
+                let uuid_matches = clear_args.uuid.as_ref().is_none_or(|u| q.uuid == *u);
+                !(url_matches && uuid_matches)
+            });
+            info!(
+                "{} quarantined login(s) cleared",
+                num_quarantines - quarantines.len()
+            );
+            config.set_quarantines(quarantines)?;
+            config.write_to(config_path)
+        }
+    }
 }
 
 fn lock_database<T: AsRef<Path>>(config_path: T) -> Result<()> {
@@ -778,6 +1431,7 @@ fn get_groups<T: AsRef<Path>>(
     verify_caller(&config)?;
     // start session
     let (client_id, _, _) = start_session()?;
+    ensure_supported(Feature::DatabaseGroups)?;
 
     let _ = associated_databases(&config, &client_id, unlock_options)?;
 
@@ -833,11 +1487,7 @@ fn generate_password<T: AsRef<Path>>(
         ..Default::default()
     };
 
-    if args.json {
-        io::stdout().write_all(serde_json::to_string(&git_resp)?.as_bytes())?;
-    } else {
-        io::stdout().write_all(git_resp.to_string().as_bytes())?;
-    }
+    io::stdout().write_all(git_resp.format(&args.output_format())?.as_bytes())?;
 
     Ok(())
 }
@@ -901,7 +1551,7 @@ fn real_main() -> Result<()> {
             .or_else(|c| Err(anyhow!("Failed to disable dump, code: {}", c)))?;
     }
 
-    let args = cli::MainArgs::parse();
+    let args = cli::MainArgs::parse_from(cli::multi_call_args(env::args_os()));
 
     let level =
         Level::from_usize(std::cmp::min(6, args.verbose + 2) as usize).unwrap_or(Level::Error);
@@ -943,6 +1593,13 @@ fn real_main() -> Result<()> {
         info!("Socket path is set to {} by user", path);
         env::set_var(utils::socket::KEEPASS_SOCKET_ENVIRONMENT_VARIABLE, path);
     };
+    if args.response_timeout > 0 {
+        info!(
+            "Response timeout is set to {}ms by user",
+            args.response_timeout
+        );
+        set_response_timeout(Some(Duration::from_millis(args.response_timeout)));
+    }
     if let Some(ref unlock_options) = args.unlock {
         info!(
             "Database unlock option is given by user: max retries {}, interval {}ms",
@@ -964,31 +1621,88 @@ fn real_main() -> Result<()> {
             if entry_filters.has_non_default() && get_args.raw {
                 Err(clap::Error::raw(
                     clap::ErrorKind::ArgumentConflict,
-                    "Filter options (--group, --git-groups, --no-filter) cannot be used with --raw",
+                    format!(
+                        "Filter options ({}) cannot be used with --raw",
+                        entry_filters.non_default_options().join(", ")
+                    ),
                 ))?;
             }
             get_logins(config_path, &args.unlock, entry_filters, get_args)
         }
         cli::Subcommands::Totp(totp_args) => {
-            let entry_filters = totp_args.local_entry_filters(main_entry_filters);
+            let entry_filters = totp_args.filters.local_entry_filters(main_entry_filters);
             if entry_filters.has_non_default() && totp_args.raw {
                 Err(clap::Error::raw(
                     clap::ErrorKind::ArgumentConflict,
-                    "Filter options (--group, --git-groups, --no-filter) cannot be used with --raw",
+                    format!(
+                        "Filter options ({}) cannot be used with --raw",
+                        entry_filters.non_default_options().join(", ")
+                    ),
                 ))?;
             }
             get_logins(config_path, &args.unlock, entry_filters, totp_args)
         }
         cli::Subcommands::Store(store_args) => {
-            let entry_filters = store_args.local_entry_filters(main_entry_filters);
+            let entry_filters = store_args.filters.local_entry_filters(main_entry_filters);
             store_login(config_path, &args.unlock, entry_filters, store_args)
         }
-        cli::Subcommands::Erase(_) => erase_login(),
+        cli::Subcommands::Erase(erase_args) => {
+            let erase_args = cli::SubEraseArgs {
+                delete: erase_args.delete || args.delete_rejected,
+                dry_run: erase_args.dry_run,
+            };
+            erase_login(config_path, &args.unlock, main_entry_filters, &erase_args)
+        }
         cli::Subcommands::Lock(_) => lock_database(config_path),
         cli::Subcommands::Groups(groups_args) => get_groups(config_path, &args.unlock, groups_args),
         cli::Subcommands::GeneratePassword(generate_password_args) => {
             generate_password(config_path, generate_password_args)
         }
+        cli::Subcommands::Quarantine(quarantine_args) => quarantine(config_path, quarantine_args),
+        cli::Subcommands::Docker(docker_args) => {
+            let entry_filters = docker_args.filters.local_entry_filters(main_entry_filters);
+            docker::docker(config_path, &args.unlock, entry_filters, docker_args)
+        }
+        cli::Subcommands::Cargo(cargo_args) => {
+            let entry_filters = cargo_args.filters.local_entry_filters(main_entry_filters);
+            cargo::cargo(config_path, &args.unlock, entry_filters, cargo_args)
+        }
+        cli::Subcommands::Kubernetes(kubernetes_args) => {
+            let entry_filters = kubernetes_args
+                .filters
+                .local_entry_filters(main_entry_filters);
+            kubernetes::kubernetes(config_path, &args.unlock, entry_filters, kubernetes_args)
+        }
+        cli::Subcommands::Aws(aws_args) => {
+            let entry_filters = aws_args.filters.local_entry_filters(main_entry_filters);
+            aws::aws(config_path, &args.unlock, entry_filters, aws_args)
+        }
+        cli::Subcommands::Terraform(terraform_args) => {
+            let entry_filters = terraform_args
+                .filters
+                .local_entry_filters(main_entry_filters);
+            terraform::terraform(config_path, &args.unlock, entry_filters, terraform_args)
+        }
+        cli::Subcommands::Askpass(askpass_args) => {
+            let entry_filters = askpass_args.filters.local_entry_filters(main_entry_filters);
+            askpass::askpass(config_path, &args.unlock, entry_filters, askpass_args)
+        }
+        cli::Subcommands::Pinentry(pinentry_args) => {
+            let entry_filters = pinentry_args
+                .filters
+                .local_entry_filters(main_entry_filters);
+            pinentry::pinentry(config_path, &args.unlock, entry_filters, pinentry_args)
+        }
+        cli::Subcommands::Exec(exec_args) => {
+            let entry_filters = exec_args.filters.local_entry_filters(main_entry_filters);
+            exec::exec(config_path, &args.unlock, entry_filters, exec_args)
+        }
+        cli::Subcommands::Inject(inject_args) => {
+            let entry_filters = inject_args.filters.local_entry_filters(main_entry_filters);
+            inject::inject(config_path, &args.unlock, entry_filters, inject_args)
+        }
+        cli::Subcommands::Agent(agent_args) => agent::agent(config_path, agent_args),
+        cli::Subcommands::Watch(watch_args) => watch::watch(config_path, watch_args),
     }
 }
 
@@ -1011,6 +1725,8 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
 
     #[test]
     #[cfg(feature = "strict-caller")]
@@ -1050,4 +1766,116 @@ mod tests {
 
         assert!(verify_caller(&config).is_ok());
     }
+
+    #[test]
+    fn test_03_login_fields() {
+        let mut login_entry: LoginEntry =
+            serde_json::from_str(r#"{"login":"foo","name":"Example","password":"bar","uuid":"0"}"#)
+                .unwrap();
+        login_entry.string_fields = Some(vec![
+            HashMap::from([("KPH: token".to_owned(), "baz".to_owned())]),
+            HashMap::from([("KPH: git".to_owned(), "true".to_owned())]),
+        ]);
+        assert_eq!(
+            get_login_field_for("", &login_entry, "username").unwrap(),
+            "foo"
+        );
+        assert_eq!(
+            get_login_field_for("", &login_entry, "password").unwrap(),
+            "bar"
+        );
+        assert_eq!(
+            get_login_field_for("", &login_entry, "token").unwrap(),
+            "baz"
+        );
+        assert_eq!(
+            get_login_field_for("", &login_entry, "KPH: token").unwrap(),
+            "baz"
+        );
+        assert!(get_login_field_for("", &login_entry, "missing").is_err());
+    }
+
+    #[test]
+    fn test_04_database_unlocked_notification() {
+        assert!(is_database_unlocked_notification(
+            r#"{"action":"database-unlocked"}"#
+        ));
+        assert!(!is_database_unlocked_notification(
+            r#"{"action":"database-locked"}"#
+        ));
+        assert!(!is_database_unlocked_notification(r#"{"message":"..."}"#));
+        assert!(!is_database_unlocked_notification("database-unlocked"));
+    }
+
+    #[test]
+    fn test_05_merge_raw_logins() {
+        let mut merged: serde_json::Value = serde_json::from_str(
+            r#"{"count":"1","entries":[{"login":"a","uuid":"0"}],"success":"true"}"#,
+        )
+        .unwrap();
+        let raw = serde_json::from_str(
+            r#"{"count":"2","entries":[{"login":"a","uuid":"0"},{"login":"b","uuid":"1"}]}"#,
+        )
+        .unwrap();
+        merge_raw_logins(&mut merged, raw);
+        assert_eq!(merged["count"], "2");
+        assert_eq!(merged["entries"][0]["login"], "a");
+        assert_eq!(merged["entries"][1]["login"], "b");
+        assert_eq!(merged["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(merged["success"], "true");
+    }
+
+    #[test]
+    fn test_06_wait_for_unlock_without_notification() {
+        let unlock_options = UnlockOptions::from_str("0,10").unwrap();
+        // KeePassXC which never sends database-unlocked
+        let mut polls = 0;
+        let unlocked = wait_for_unlock_with(
+            "mock database",
+            &unlock_options,
+            None,
+            || {
+                polls += 1;
+                polls == 3
+            },
+            |until| {
+                thread::sleep(until.saturating_duration_since(Instant::now()));
+                Ok(false)
+            },
+        )
+        .unwrap();
+        assert!(unlocked);
+        assert_eq!(polls, 3);
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let unlocked = wait_for_unlock_with(
+            "mock database",
+            &unlock_options,
+            Some(deadline),
+            || false,
+            |until| {
+                thread::sleep(until.saturating_duration_since(Instant::now()));
+                Ok(false)
+            },
+        )
+        .unwrap();
+        assert!(!unlocked);
+        assert!(Instant::now() >= deadline);
+
+        // a notification ends the wait without polling again
+        let mut polls = 0;
+        let unlocked = wait_for_unlock_with(
+            "mock database",
+            &unlock_options,
+            None,
+            || {
+                polls += 1;
+                false
+            },
+            |_| Ok(true),
+        )
+        .unwrap();
+        assert!(unlocked);
+        assert_eq!(polls, 1);
+    }
 }
@@ -0,0 +1,336 @@
+//! GnuPG pinentry, which speaks the Assuan protocol, see
+//! https://www.gnupg.org/documentation/manuals/assuan/Client-requests.html
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{get_first_login_for, start_session, verify_caller};
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// GPG error codes with pinentry as the error source
+const GPG_ERR_CANCELED: &str = "ERR 83886179 Operation cancelled";
+const GPG_ERR_NOT_SUPPORTED: &str = "ERR 83886140 Not supported";
+const GPG_ERR_ASS_PARAMETER: &str = "ERR 83886360 IPC parameter error";
+const GPG_ERR_ASS_UNKNOWN_CMD: &str = "ERR 83886355 Unknown IPC command";
+
+/// A real pinentry that takes over when no entry is found
+struct Fallback {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Fallback {
+    fn spawn(program: &str, args: &[String]) -> Result<Self> {
+        info!("Starting fallback pinentry {}", program);
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = BufReader::new(child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?);
+        let mut fallback = Self { child, stdout };
+        // greeting
+        fallback.read_response()?;
+        Ok(fallback)
+    }
+
+    fn read_response(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(anyhow!("Fallback pinentry exited unexpectedly"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']).to_owned();
+            let is_last = line == "OK" || line.starts_with("OK ") || line.starts_with("ERR ");
+            lines.push(line);
+            if is_last {
+                return Ok(lines);
+            }
+        }
+    }
+
+    fn transact(&mut self, command: &str) -> Result<Vec<String>> {
+        let stdin = self
+            .child
+            .stdin
+            .as_mut()
+            .ok_or_else(|| anyhow!("No stdin"))?;
+        stdin.write_all(format!("{}\n", command).as_bytes())?;
+        stdin.flush()?;
+        self.read_response()
+    }
+}
+
+impl Drop for Fallback {
+    fn drop(&mut self) {
+        let _ = self.transact("BYE");
+        // closing stdin as well in case it doesn't exit on BYE
+        drop(self.child.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+fn percent_escape(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\r' => escaped.push_str("%0D"),
+            '\n' => escaped.push_str("%0A"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// SETKEYINFO takes e.g. n/<KEYGRIP>, s/<KEYGRIP>, or --clear
+fn parse_keyinfo(keyinfo: &str) -> Option<String> {
+    if keyinfo.is_empty() || keyinfo == "--clear" {
+        return None;
+    }
+    let keygrip = keyinfo.split_once('/').map_or(keyinfo, |(_, k)| k);
+    Some(keygrip.to_owned())
+}
+
+struct Session<'a, F: FnMut(&str) -> Result<Option<String>>> {
+    lookup: F,
+    fallback_program: Option<&'a str>,
+    fallback_args: &'a [String],
+    fallback: Option<Fallback>,
+    // setters sent so far, replayed to the fallback pinentry when it's started
+    history: Vec<String>,
+    keygrip: Option<String>,
+}
+
+impl<F: FnMut(&str) -> Result<Option<String>>> Session<'_, F> {
+    fn fallback(&mut self) -> Result<Option<&mut Fallback>> {
+        if self.fallback.is_none() {
+            let program = match self.fallback_program {
+                Some(program) => program,
+                None => return Ok(None),
+            };
+            let mut fallback = Fallback::spawn(program, self.fallback_args)?;
+            for command in &self.history {
+                fallback.transact(command)?;
+            }
+            self.fallback = Some(fallback);
+        }
+        Ok(self.fallback.as_mut())
+    }
+
+    fn relay(&mut self, command: &str, otherwise: &str) -> Result<Vec<String>> {
+        match self.fallback()? {
+            Some(fallback) => fallback.transact(command),
+            None => Ok(vec![otherwise.to_owned()]),
+        }
+    }
+
+    fn get_pin(&mut self, command: &str) -> Result<Vec<String>> {
+        if let Some(keygrip) = self.keygrip.clone() {
+            match (self.lookup)(&keygrip) {
+                Ok(Some(pin)) => {
+                    return Ok(vec![format!("D {}", percent_escape(&pin)), "OK".to_owned()]);
+                }
+                Ok(None) => {
+                    info!("No passphrase found for key {}", keygrip);
+                }
+                Err(e) => {
+                    error!("Failed to get passphrase for key {}: {}", keygrip, e);
+                }
+            }
+        } else {
+            info!("No key info provided, cannot look up passphrase");
+        }
+        self.relay(command, GPG_ERR_CANCELED)
+    }
+
+    /// Returns response lines and whether the connection should be closed
+    fn handle(&mut self, line: &str) -> Result<(Vec<String>, bool)> {
+        let (command, parameters) = line.split_once(' ').unwrap_or((line, ""));
+        let command = command.to_ascii_uppercase();
+        debug!("Pinentry command: {}", command);
+        let response = match command.as_str() {
+            "BYE" => return Ok((vec!["OK closing connection".to_owned()], true)),
+            "NOP" => vec!["OK".to_owned()],
+            "RESET" => {
+                self.history.clear();
+                self.keygrip = None;
+                if let Some(fallback) = self.fallback.as_mut() {
+                    fallback.transact(line)?;
+                }
+                vec!["OK".to_owned()]
+            }
+            "GETPIN" => self.get_pin(line)?,
+            "CONFIRM" | "MESSAGE" => self.relay(line, GPG_ERR_NOT_SUPPORTED)?,
+            "GETINFO" => match parameters {
+                "flavor" => vec!["D keepassxc".to_owned(), "OK".to_owned()],
+                "version" => vec![format!("D {}", env!("CARGO_PKG_VERSION")), "OK".to_owned()],
+                "pid" => vec![format!("D {}", std::process::id()), "OK".to_owned()],
+                _ => vec![GPG_ERR_ASS_PARAMETER.to_owned()],
+            },
+            "OPTION" | "SETKEYINFO" | "SETDESC" | "SETPROMPT" | "SETTITLE" | "SETOK"
+            | "SETCANCEL" | "SETNOTOK" | "SETERROR" | "SETREPEAT" | "SETREPEATERROR"
+            | "SETREPEATOK" | "SETQUALITYBAR" | "SETQUALITYBAR_TT" | "SETGENPIN"
+            | "SETGENPIN_TT" | "SETTIMEOUT" | "CLEARPASSPHRASE" => {
+                if command == "SETKEYINFO" {
+                    self.keygrip = parse_keyinfo(parameters);
+                }
+                self.history.push(line.to_owned());
+                if let Some(fallback) = self.fallback.as_mut() {
+                    fallback.transact(line)?;
+                }
+                vec!["OK".to_owned()]
+            }
+            _ => vec![GPG_ERR_ASS_UNKNOWN_CMD.to_owned()],
+        };
+        Ok((response, false))
+    }
+}
+
+fn serve<R, W, F>(
+    reader: R,
+    mut writer: W,
+    lookup: F,
+    fallback_program: Option<&str>,
+    fallback_args: &[String],
+) -> Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str) -> Result<Option<String>>,
+{
+    let mut session = Session {
+        lookup,
+        fallback_program,
+        fallback_args,
+        fallback: None,
+        history: Vec::new(),
+        keygrip: None,
+    };
+    writer.write_all(b"OK Pleased to meet you\n")?;
+    writer.flush()?;
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (response, bye) = session.handle(line)?;
+        for response_line in response {
+            writer.write_all(format!("{}\n", response_line).as_bytes())?;
+        }
+        writer.flush()?;
+        if bye {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn pinentry<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubPinentryArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    let lookup = |keygrip: &str| -> Result<Option<String>> {
+        let url = normalise_url(format!("gpg://{}", keygrip), config.get_url_policies());
+        let (client_id, _, _) = start_session()?;
+        let login_entry = get_first_login_for(
+            &config,
+            &client_id,
+            &url,
+            None,
+            &entry_filters,
+            unlock_options,
+        )?;
+        Ok(login_entry.map(|login_entry| login_entry.password))
+    };
+    let stdin = io::stdin();
+    serve(
+        stdin.lock(),
+        io::stdout(),
+        lookup,
+        args.fallback.as_deref(),
+        &args.fallback_args(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converse(input: &str, fallback: Option<&str>) -> String {
+        let mut output = Vec::new();
+        serve(
+            input.as_bytes(),
+            &mut output,
+            |keygrip| {
+                Ok(match keygrip {
+                    "0123ABCD" => Some("100%\nsecret".to_owned()),
+                    _ => None,
+                })
+            },
+            fallback,
+            &[],
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_00_getpin() {
+        let output = converse(
+            "OPTION ttyname=/dev/pts/0\nSETDESC Please enter the passphrase\nSETKEYINFO n/0123ABCD\nGETPIN\nBYE\n",
+            None,
+        );
+        assert_eq!(
+            output,
+            "OK Pleased to meet you\nOK\nOK\nOK\nD 100%25%0Asecret\nOK\nOK closing connection\n"
+        );
+    }
+
+    #[test]
+    fn test_01_unknown_key_without_fallback() {
+        let output = converse(
+            "SETKEYINFO s/FFFF\nGETPIN\nRESET\nGETPIN\nFOO\nGETINFO flavor\n",
+            None,
+        );
+        assert_eq!(
+            output,
+            format!(
+                "OK Pleased to meet you\nOK\n{}\nOK\n{}\n{}\nD keepassxc\nOK\n",
+                GPG_ERR_CANCELED, GPG_ERR_CANCELED, GPG_ERR_ASS_UNKNOWN_CMD
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_02_fallback() {
+        // a fake pinentry that greets, then answers every command with a PIN and OK
+        let script = "echo OK; while read -r line; do case \"$line\" in GETPIN) echo 'D fallback'; echo OK;; *) echo OK;; esac; done";
+        let mut output = Vec::new();
+        serve(
+            "SETKEYINFO n/FFFF\nGETPIN\nBYE\n".as_bytes(),
+            &mut output,
+            |_| Ok(None),
+            Some("sh"),
+            &["-c".to_owned(), script.to_owned()],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "OK Pleased to meet you\nOK\nD fallback\nOK\nOK closing connection\n"
+        );
+    }
+}
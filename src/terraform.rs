@@ -0,0 +1,137 @@
+//! Terraform credentials helper protocol, see
+//! https://developer.hashicorp.com/terraform/internals/credentials-helpers
+
+use crate::cli::{self, EntryFilters, UnlockOptions};
+use crate::config::Config;
+use crate::git::GitCredentialMessage;
+use crate::keepassxc::errors::KeePassError;
+use crate::utils::url::normalise_url;
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{erase_login_for, get_first_login_for, start_session, store_login_for, verify_caller};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// username of new entries as Terraform only deals with tokens
+const TERRAFORM_USERNAME: &str = "terraform";
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct TerraformCredential {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// Terraform sends bare host names, e.g. app.terraform.io
+fn hostname_to_url<T: AsRef<str>>(hostname: T) -> String {
+    format!("https://{}", hostname.as_ref())
+}
+
+pub fn terraform<T: AsRef<Path>>(
+    config_path: T,
+    unlock_options: &Option<UnlockOptions>,
+    entry_filters: EntryFilters,
+    args: &cli::SubTerraformArgs,
+) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+
+    match &args.command {
+        cli::TerraformSubcommands::Get(get_args) => {
+            let url = normalise_url(
+                hostname_to_url(&get_args.hostname),
+                config.get_url_policies(),
+            );
+            let (client_id, _, _) = start_session()?;
+            let login_entry = get_first_login_for(
+                &config,
+                &client_id,
+                &url,
+                None,
+                &entry_filters,
+                unlock_options,
+            )?;
+            if login_entry.is_none() {
+                info!("No matching logins found for {}", url);
+            }
+            // an empty object tells Terraform that there are no credentials
+            let credential = TerraformCredential {
+                token: login_entry.map(|login_entry| login_entry.password),
+            };
+            io::stdout().write_all(serde_json::to_string(&credential)?.as_bytes())?;
+            Ok(())
+        }
+        cli::TerraformSubcommands::Store(store_args) => {
+            let mut input = String::with_capacity(256);
+            io::stdin().read_to_string(&mut input)?;
+            let credential: TerraformCredential = serde_json::from_str(&input)?;
+            let token = credential
+                .token
+                .ok_or_else(|| anyhow!("Token is missing, only token credentials are supported"))?;
+            let git_req = GitCredentialMessage {
+                url: Some(hostname_to_url(&store_args.hostname)),
+                username: Some(TERRAFORM_USERNAME.to_owned()),
+                password: Some(token),
+                ..Default::default()
+            };
+            // Terraform shows the error to the user running terraform login
+            store_login_for(
+                config,
+                config_path,
+                unlock_options,
+                &entry_filters,
+                args.create_in.as_ref(),
+                git_req,
+            )
+            .with_context(|| format!("Failed to store token for {}", store_args.hostname))
+        }
+        cli::TerraformSubcommands::Forget(forget_args) => {
+            // same as Git, logins are quarantined rather than deleted, only the one of the
+            // username terraform login stores
+            let git_req = GitCredentialMessage {
+                url: Some(hostname_to_url(&forget_args.hostname)),
+                username: Some(TERRAFORM_USERNAME.to_owned()),
+                ..Default::default()
+            };
+            let result = erase_login_for(
+                config,
+                config_path,
+                unlock_options,
+                &entry_filters,
+                &cli::SubEraseArgs::default(),
+                git_req,
+            );
+            match result {
+                Err(e) => match e.downcast_ref::<KeePassError>() {
+                    Some(keepass_error) if keepass_error.is_no_logins_found() => {
+                        info!("No matching logins found, nothing to forget");
+                        Ok(())
+                    }
+                    _ => Err(e),
+                },
+                _ => result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_credential_json() {
+        let credential: TerraformCredential =
+            serde_json::from_str(r#"{"token":"foo","extra":"bar"}"#).unwrap();
+        assert_eq!(credential.token.as_deref(), Some("foo"));
+        assert_eq!(
+            serde_json::to_string(&TerraformCredential::default()).unwrap(),
+            "{}"
+        );
+        assert_eq!(
+            hostname_to_url("app.terraform.io"),
+            "https://app.terraform.io"
+        );
+    }
+}
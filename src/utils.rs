@@ -1,6 +1,8 @@
 pub mod base64;
 pub mod callers;
+pub mod json_stream;
 pub mod socket;
+pub mod url;
 
 use anyhow::{anyhow, Context, Error, Result};
 use crypto_box::{
@@ -8,11 +10,14 @@ use crypto_box::{
     aead::{generic_array, Aead, AeadCore},
     PublicKey, SalsaBox, SecretKey, KEY_SIZE,
 };
+use json_stream::JsonStreamDecoder;
 #[cfg(test)]
 use mockall::mock;
 #[cfg(windows)]
 use named_pipe::PipeClient;
 use once_cell::unsync::OnceCell;
+#[cfg(unix)]
+use std::cell::Cell;
 use std::cell::RefCell;
 use std::fmt;
 use std::io::{ErrorKind, Read, Write};
@@ -20,11 +25,12 @@ use std::io::{ErrorKind, Read, Write};
 use std::os::unix::net::UnixStream;
 use std::rc::Rc;
 use std::str;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 #[cfg(windows)]
 const NAMED_PIPE_CONNECT_TIMEOUT_MS: u32 = 100;
-const READ_TIMEOUT: Duration = Duration::new(0, 200 * 1_000_000);
+const READ_BUF_SIZE: usize = 65_536;
 
 #[macro_export]
 macro_rules! error {
@@ -75,22 +81,102 @@ impl fmt::Display for CryptionError {
     }
 }
 impl std::error::Error for CryptionError {}
+#[derive(Debug)]
+pub struct InvalidNonceError(String);
+impl fmt::Display for InvalidNonceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid nonce {}", self.0)
+    }
+}
+impl std::error::Error for InvalidNonceError {}
+
+// the agent itself must connect to KeePassXC directly
+static USE_AGENT: AtomicBool = AtomicBool::new(true);
+// in milliseconds, 0 to wait indefinitely
+static RESPONSE_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+#[cfg(unix)]
+thread_local!(static CONNECTED_TO_AGENT: Cell<bool> = const { Cell::new(false) });
+
+pub fn set_use_agent(use_agent: bool) {
+    USE_AGENT.store(use_agent, Ordering::Relaxed);
+}
+
+/// How long to wait for responses from KeePassXC, None to wait indefinitely
+pub fn set_response_timeout(timeout: Option<Duration>) {
+    let millis = timeout.map_or(0, |t| u64::try_from(t.as_millis()).unwrap_or(u64::MAX));
+    RESPONSE_TIMEOUT.store(millis, Ordering::Relaxed);
+}
+
+fn get_response_timeout() -> Option<Duration> {
+    match RESPONSE_TIMEOUT.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+/// Whether messages go through git-credential-keepassxc agent rather than to KeePassXC directly
+#[cfg(unix)]
+pub fn is_connected_to_agent() -> Result<bool> {
+    get_stream()?;
+    Ok(CONNECTED_TO_AGENT.with(Cell::get))
+}
+
+#[cfg(windows)]
+pub fn is_connected_to_agent() -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn connect_to_agent() -> Option<UnixStream> {
+    if !USE_AGENT.load(Ordering::Relaxed) {
+        return None;
+    }
+    let path = match socket::get_agent_socket_path() {
+        Ok(path) => path,
+        Err(e) => {
+            debug!("Failed to locate agent socket: {}", e);
+            return None;
+        }
+    };
+    if !path.exists() {
+        return None;
+    }
+    match UnixStream::connect(&path) {
+        Ok(stream) => {
+            info!("Connected to agent {}", path.to_string_lossy());
+            Some(stream)
+        }
+        Err(e) => {
+            warn!(
+                "Failed to connect to agent {}, falling back to KeePassXC: {}",
+                path.to_string_lossy(),
+                e
+            );
+            None
+        }
+    }
+}
 
 #[cfg(unix)]
 fn get_stream() -> Result<Rc<RefCell<UnixStream>>> {
-    thread_local!(static STREAM: OnceCell<Rc<RefCell<UnixStream>>> = OnceCell::new());
+    thread_local!(static STREAM: OnceCell<Rc<RefCell<UnixStream>>> = const { OnceCell::new() });
     STREAM.with(|s| -> Result<_> {
         Ok(s.get_or_try_init(|| -> Result<_> {
-            let path = socket::get_socket_path()?;
-            let stream = UnixStream::connect(&path).with_context(|| {
-                format!(
-                    "Failed to connect to Unix socket {}",
-                    path.to_string_lossy()
-                )
-            })?;
-            if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
-                warn!("Failed to set read timeout: {}", e);
-            }
+            let stream = match connect_to_agent() {
+                Some(stream) => {
+                    CONNECTED_TO_AGENT.with(|c| c.set(true));
+                    stream
+                }
+                None => {
+                    let path = socket::get_socket_path()?;
+                    UnixStream::connect(&path).with_context(|| {
+                        format!(
+                            "Failed to connect to Unix socket {}",
+                            path.to_string_lossy()
+                        )
+                    })?
+                }
+            };
             Ok(Rc::new(RefCell::new(stream)))
         })?
         .clone())
@@ -99,15 +185,14 @@ fn get_stream() -> Result<Rc<RefCell<UnixStream>>> {
 
 #[cfg(windows)]
 fn get_stream() -> Result<Rc<RefCell<PipeClient>>> {
-    thread_local!(static STREAM: OnceCell<Rc<RefCell<PipeClient>>> = OnceCell::new());
+    thread_local!(static STREAM: OnceCell<Rc<RefCell<PipeClient>>> = const { OnceCell::new() });
     STREAM.with(|s| -> Result<_> {
         Ok(s.get_or_try_init(|| -> Result<_> {
             let path = socket::get_socket_path()?;
-            let mut stream = PipeClient::connect_ms(&path, NAMED_PIPE_CONNECT_TIMEOUT_MS)
+            let stream = PipeClient::connect_ms(&path, NAMED_PIPE_CONNECT_TIMEOUT_MS)
                 .with_context(|| {
                     format!("Failed to connect to named pipe {}", path.to_string_lossy())
                 })?;
-            stream.set_read_timeout(Some(READ_TIMEOUT));
             Ok(Rc::new(RefCell::new(stream)))
         })?
         .clone())
@@ -121,7 +206,8 @@ pub trait MessagingUtilsTrait {
 }
 
 trait MessagingUtilsInternalTrait {
-    fn read_to_end() -> Result<String>;
+    /// Returns what has arrived by the deadline, which may be nothing
+    fn read_chunk(deadline: Option<Instant>) -> Result<Vec<u8>>;
 }
 
 pub struct MessagingUtils {}
@@ -134,7 +220,7 @@ mock! {
         fn receive_message() -> Result<String>;
     }
     impl MessagingUtilsInternalTrait for MessagingUtils {
-        fn read_to_end() -> Result<String>;
+        fn read_chunk(deadline: Option<Instant>) -> Result<Vec<u8>>;
     }
 }
 
@@ -153,105 +239,103 @@ impl MessagingUtilsTrait for MessagingUtils {
     }
 
     fn receive_message() -> Result<String> {
-        loop {
-            #[cfg(not(test))]
-            let response = Self::read_to_end()?;
-            #[cfg(test)]
-            let response = MockMessagingUtils::read_to_end()?;
-            let jsons = cut_jsons(&response);
-            if jsons.len() == 1 {
-                break Ok(response);
-            }
-            warn!(
-                "Response contains {} (> 1) JSONs, hence discarded",
-                jsons.len(),
-            );
+        let deadline = get_response_timeout().map(|timeout| Instant::now() + timeout);
+        if !wait_for_messages(deadline)? {
+            return Err(anyhow!("Timed out waiting for response from KeePassXC"));
         }
+        Ok(DECODER
+            .with(|d| d.borrow_mut().next_message())
+            .expect("Messages have arrived"))
     }
 }
 
 impl MessagingUtilsInternalTrait for MessagingUtils {
-    fn read_to_end() -> Result<String> {
+    fn read_chunk(deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) if !timeout.is_zero() => Some(timeout),
+                _ => return Ok(Vec::new()),
+            },
+            None => None,
+        };
         let stream_rc = get_stream()?;
         let mut stream = stream_rc.borrow_mut();
-        let mut response = String::new();
-        const BUF_SIZE: usize = 1_048_576;
-        let mut buf = vec![0u8; BUF_SIZE];
-        let mut read = false;
+        #[cfg(unix)]
+        stream.set_read_timeout(timeout)?;
+        #[cfg(windows)]
+        stream.set_read_timeout(timeout);
+        let mut buf = vec![0u8; READ_BUF_SIZE];
         loop {
-            let len = match stream.read(&mut buf) {
-                Ok(len) if len <= BUF_SIZE => len,
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(anyhow!("Connection closed by KeePassXC")),
                 Ok(len) => {
-                    warn!("Read returned {} > BUF_SIZE ({})", len, BUF_SIZE);
-                    BUF_SIZE
+                    buf.truncate(len);
+                    return Ok(buf);
                 }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
                 Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => {
-                    0usize
+                    return Ok(Vec::new())
                 }
                 Err(e) => return Err(e.into()),
-            };
-            #[cfg(debug_assertions)]
-            {
-                debug!("Received {} bytes: {:?}", len, &buf[0..len]);
-                if let Ok(buf_str) = str::from_utf8(&buf[0..len]) {
-                    debug!("Received {} chars: {}", buf_str.len(), buf_str);
-                }
-            }
-            response.push_str(str::from_utf8(&buf[0..len]).unwrap());
-            read = read || len > 0;
-            if read && len < BUF_SIZE {
-                break;
             }
         }
-        debug!("RECV: {}", response);
-        Ok(response)
     }
 }
 
-fn cut_jsons(response: &str) -> Vec<&str> {
-    let mut results = Vec::new();
+thread_local!(static DECODER: RefCell<JsonStreamDecoder> = RefCell::new(JsonStreamDecoder::new()));
 
-    let matching_symbol = |c: &char| -> Option<char> {
-        match c {
-            '{' => Some('}'),
-            '}' => Some('{'),
-            '[' => Some(']'),
-            ']' => Some('['),
-            '"' => Some('"'),
-            _ => None,
+/// Waits until at least one message has been decoded, returns false if the deadline has passed
+fn wait_for_messages(deadline: Option<Instant>) -> Result<bool> {
+    loop {
+        if DECODER.with(|d| d.borrow().has_messages()) {
+            return Ok(true);
         }
-    };
-    let is_symbol = |c: &char| -> bool { matching_symbol(c).is_some() };
-
-    let mut stack = Vec::new();
-    let mut start = 0;
-    let mut current = 0;
-    let mut escape = false;
-    for c in response.chars() {
-        if stack.is_empty() && current > 0 {
-            results.push(&response[start..current]);
-            start = current;
-        }
-        if !escape && c == '\\' {
-            escape = true;
-            current += 1;
-            continue;
-        }
-        if !is_symbol(&c) || escape {
-            escape = false;
-            current += 1;
+        #[cfg(not(test))]
+        let chunk = MessagingUtils::read_chunk(deadline)?;
+        #[cfg(test)]
+        let chunk = MockMessagingUtils::read_chunk(deadline)?;
+        if chunk.is_empty() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
             continue;
         }
-        if !stack.is_empty() && stack.last().unwrap() == &matching_symbol(&c).unwrap() {
-            stack.pop();
-        } else {
-            stack.push(c);
-        }
-        current += 1;
+        DECODER.with(|d| d.borrow_mut().feed(&chunk));
     }
-    results.push(&response[start..]);
+}
+
+/// Waits for the next message(s), including notifications that KeePassXC sends on its own, e.g.
+/// database-locked
+pub fn receive_messages() -> Result<Vec<String>> {
+    wait_for_messages(None)?;
+    Ok(DECODER.with(|d| d.borrow_mut().take_messages()))
+}
+
+/// Like receive_messages, but gives up when nothing has arrived by the deadline
+pub fn receive_messages_until(deadline: Instant) -> Result<Vec<String>> {
+    wait_for_messages(Some(deadline))?;
+    Ok(DECODER.with(|d| d.borrow_mut().take_messages()))
+}
 
-    results
+/// Reads messages which have arrived but not been received yet, e.g. database-locked notifications,
+/// without waiting for more
+#[cfg(unix)]
+pub fn read_pending_messages() -> Result<Vec<String>> {
+    let stream_rc = get_stream()?;
+    let mut stream = stream_rc.borrow_mut();
+    stream.set_nonblocking(true)?;
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    let result = loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break Err(anyhow!("Connection closed")),
+            Ok(len) => DECODER.with(|d| d.borrow_mut().feed(&buf[..len])),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+            Err(e) => break Err(e.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    result?;
+    Ok(DECODER.with(|d| d.borrow_mut().take_messages()))
 }
 
 pub fn to_public_key<T: AsRef<str>>(public_key_b64: T) -> Result<PublicKey> {
@@ -295,7 +379,7 @@ pub fn get_client_box(
     host_public_key: Option<&PublicKey>,
     client_secret_key: Option<&SecretKey>,
 ) -> Result<Rc<SalsaBox>> {
-    thread_local!(static CLIENT_BOX: OnceCell<Rc<SalsaBox>> = OnceCell::new());
+    thread_local!(static CLIENT_BOX: OnceCell<Rc<SalsaBox>> = const { OnceCell::new() });
     CLIENT_BOX.with(|cb| -> Result<_> {
         Ok(cb.get_or_try_init(|| -> Result<_> {
             let client_secret_key = client_secret_key.ok_or_else(||
@@ -310,6 +394,7 @@ pub fn get_client_box(
 }
 
 type NaClNonce = generic_array::GenericArray<u8, generic_array::typenum::U24>;
+const NONCE_SIZE: usize = 24;
 
 pub fn nacl_nonce() -> (NaClNonce, String) {
     let mut rng = rand::thread_rng();
@@ -318,6 +403,12 @@ pub fn nacl_nonce() -> (NaClNonce, String) {
     (nonce, nonce_b64)
 }
 
+/// Random ID sent along with a request, so that its response can be told apart from late responses
+/// to earlier requests
+pub fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
 pub fn to_encrypted_json<M: serde::Serialize>(request: &M, nonce: &NaClNonce) -> Result<String> {
     let json = serde_json::to_string(request)?;
     debug!("ENC : {}", json);
@@ -331,12 +422,14 @@ pub fn to_encrypted_json<M: serde::Serialize>(request: &M, nonce: &NaClNonce) ->
 
 pub fn to_decrypted_json<T: AsRef<str>>(encrypted_b64: T, nonce: T) -> Result<String> {
     let bytes = base64::decode(encrypted_b64.as_ref())?;
+    let nonce = match base64::decode(nonce.as_ref()) {
+        // from_slice panics on other lengths
+        Ok(nonce) if nonce.len() == NONCE_SIZE => nonce,
+        _ => return Err(InvalidNonceError(nonce.as_ref().to_owned()).into()),
+    };
     let client_box = get_client_box(None, None)?;
     let decrypted_json = client_box
-        .decrypt(
-            NaClNonce::from_slice(&base64::decode(nonce.as_ref())?),
-            &bytes[..],
-        )
+        .decrypt(NaClNonce::from_slice(&nonce), &bytes[..])
         .map_err(|_| CryptionError(false))?;
     let json = String::from_utf8(decrypted_json)?;
     debug!("DEC : {}", json);
@@ -376,8 +469,8 @@ mod tests {
         __mock_MockMessagingUtils_MessagingUtilsTrait::__send_message::Context;
     pub type ReceiveMessageContext =
         __mock_MockMessagingUtils_MessagingUtilsTrait::__receive_message::Context;
-    pub type ReadToEndContext =
-        __mock_MockMessagingUtils_MessagingUtilsInternalTrait::__read_to_end::Context;
+    pub type ReadChunkContext =
+        __mock_MockMessagingUtils_MessagingUtilsInternalTrait::__read_chunk::Context;
 
     pub fn mock_kpxc_initialise(host_secret_key: &SecretKey) -> ExchangeMessageContext {
         let host_public_key = host_secret_key.public_key();
@@ -446,6 +539,7 @@ mod tests {
             nonce: Some(nonce_b64),
             error: None,
             error_code: None,
+            request_id: None,
         };
 
         context
@@ -454,76 +548,58 @@ mod tests {
             .return_once(move || Ok(serde_json::to_string(&wrapper).unwrap()));
     }
 
-    pub fn mock_kpxc_with_jsons(jsons: Vec<&str>) -> ReadToEndContext {
-        let read_to_end_ctx = MockMessagingUtils::read_to_end_context();
-        for json in jsons {
-            let json = json.to_string();
-            read_to_end_ctx
+    pub fn mock_kpxc_with_chunks(chunks: Vec<&str>) -> ReadChunkContext {
+        let read_chunk_ctx = MockMessagingUtils::read_chunk_context();
+        let mut sequence = mockall::Sequence::new();
+        for chunk in chunks {
+            let chunk = chunk.as_bytes().to_vec();
+            read_chunk_ctx
                 .expect()
                 .times(1)
-                .return_once(move || Ok(json));
+                .in_sequence(&mut sequence)
+                .return_once(move |_| Ok(chunk));
         }
-        read_to_end_ctx
-    }
-
-    #[test]
-    fn test_00_cut_jsons_single_json() {
-        let response = "{\"action\":\"test-associate\"}".to_owned();
-        let results = cut_jsons(&response);
-        assert_eq!(1, results.len());
-        assert_eq!(&response, results[0]);
+        read_chunk_ctx
     }
 
     #[test]
-    fn test_01_cut_jsons_multiple_jsons() {
-        let jsons = vec![
-            "{\"action\":\"test-associate\"}".to_owned(),
-            "{\"action\":\"get-logins\",\"message\":\"testing\"}".to_owned(),
-            "{\"action\":\"set-login\",\"message\":\"testing\"}".to_owned(),
+    fn test_00_queue_multiple_jsons() {
+        let _guard = test_guard().lock().unwrap();
+        let jsons = [
+            r#"{"action":"database-locked"}"#,
+            r#"{"action":"test-associate","message":"\"\[}"}"#,
+            r#"{"action":"get-logins","message":"testing\]"}"#,
         ];
-        let response = jsons.iter().fold(String::new(), |acc, j| acc + j);
-        let results = cut_jsons(&response);
-        assert_eq!(jsons.len(), results.len());
-        for i in 0..jsons.len() {
-            assert_eq!(&jsons[i], results[i]);
-        }
-    }
-
-    #[test]
-    fn test_02_cut_jsons_with_escaping() {
-        let jsons = vec![
-            "{\"action\":\"test-associate\",\"message\":\"\\\"\\[\"}".to_owned(),
-            "[{\"action\":\"get-logins\",\"message\":\"testing\\]\"}]".to_owned(),
-        ];
-        let response = jsons.iter().fold(String::new(), |acc, j| acc + j);
-        let results = cut_jsons(&response);
-        assert_eq!(jsons.len(), results.len());
-        for i in 0..jsons.len() {
-            assert_eq!(&jsons[i], results[i]);
+        let read_chunk_ctx = mock_kpxc_with_chunks(vec![
+            &jsons[0][..10],
+            "",
+            &(jsons[0][10..].to_owned() + jsons[1] + jsons[2]),
+        ]);
+        for json in jsons {
+            assert_eq!(MessagingUtils::receive_message().unwrap(), json);
         }
+        read_chunk_ctx.checkpoint();
     }
 
     #[test]
-    fn test_03_discard_multiple_jsons() {
-        let jsons = vec![
-            "{\"action\":\"test-associate\",\"message\":\"\\\"\\[\"}".to_owned()
-                + "[{\"action\":\"get-logins\",\"message\":\"testing\\]\"}]",
-            "{\"action\":\"test-associate\",\"message\":\"\\\"\\[\"}".to_owned()
-                + "[{\"action\":\"get-logins\",\"message\":\"testing\\]\"}]",
-            "[{\"action\":\"get-logins\",\"message\":\"testing\\]\"}]".to_owned(),
-        ];
-
-        let read_to_end_ctx = mock_kpxc_with_jsons(jsons.iter().map(String::as_str).collect());
-        let response = MessagingUtils::receive_message();
-        assert!(response.is_ok());
-        let response = response.unwrap();
-        assert_eq!(&response, jsons.last().unwrap());
-        read_to_end_ctx.checkpoint();
+    fn test_01_response_timeout() {
+        let _guard = test_guard().lock().unwrap();
+        let read_chunk_ctx = MockMessagingUtils::read_chunk_context();
+        read_chunk_ctx
+            .expect()
+            .withf(|deadline| deadline.is_some())
+            .returning(|_| Ok(Vec::new()));
+        set_response_timeout(Some(Duration::from_millis(10)));
+        let result = MessagingUtils::receive_message();
+        set_response_timeout(None);
+        assert!(result.is_err());
+        assert!(receive_messages_until(Instant::now()).unwrap().is_empty());
+        read_chunk_ctx.checkpoint();
     }
 
     #[test]
     #[should_panic(expected = "get_client_box() is called before client secret key is available")]
-    fn test_04_fail_encrypt_before_initialise() {
+    fn test_02_fail_encrypt_before_initialise() {
         #[derive(Serialize)]
         struct Foo {
             bar: String,
@@ -544,7 +620,7 @@ mod tests {
     }
 
     #[test]
-    fn test_05_encryption_decryption() {
+    fn test_03_encryption_decryption() {
         #[derive(Serialize, Deserialize)]
         struct Foo {
             bar: String,
@@ -5,10 +5,11 @@ use anyhow::{anyhow, Result};
 #[cfg(unix)]
 use std::ops::Deref;
 use std::path::PathBuf;
-use sysinfo::{get_current_pid, PidExt, ProcessExt, RefreshKind, System, SystemExt};
+use sysinfo::{get_current_pid, Pid, PidExt, ProcessExt, RefreshKind, System, SystemExt};
 
 pub struct CurrentCaller {
     pub path: PathBuf,
+    #[cfg_attr(not(feature = "notification"), allow(dead_code))]
     pub pid: u32,
     #[cfg(unix)]
     pub uid: u32,
@@ -22,6 +23,11 @@ impl CurrentCaller {
         debug!("Collecting process info");
         let pid =
             get_current_pid().map_err(|s| anyhow!("Failed to retrieve current PID: {}", s))?;
+        Self::parent_of(pid)
+    }
+
+    /// Caller of another process, e.g. of a helper connected to the agent
+    pub fn parent_of(pid: Pid) -> Result<Self> {
         info!("PID: {}", pid);
         let mut system = System::new_with_specifics(RefreshKind::new());
         system.refresh_process(pid);
@@ -40,10 +46,10 @@ impl CurrentCaller {
         let ppath = pproc.exe();
         info!("Parent process path: {}", ppath.to_string_lossy());
         let canonical_ppath = ppath.canonicalize();
-        if canonical_ppath.is_ok() {
+        if let Ok(ref canonical_ppath) = canonical_ppath {
             info!(
                 "Canonical parent process path: {}",
-                canonical_ppath.as_ref().unwrap().to_string_lossy()
+                canonical_ppath.to_string_lossy()
             );
         } else {
             warn!("Failed to determine canonical parent process path");
@@ -72,7 +78,7 @@ impl CurrentCaller {
         {
             return false;
         }
-        if caller.canonicalize && self.canonical_path.is_some() {
+        if let (true, Some(canonical_path)) = (caller.canonicalize, &self.canonical_path) {
             let canonical_caller = PathBuf::from(&caller.path).canonicalize();
             if canonical_caller
                 .as_ref()
@@ -84,8 +90,9 @@ impl CurrentCaller {
                     canonical_caller.as_ref().unwrap().to_string_lossy()
                 );
             }
-            if canonical_caller.is_ok()
-                && self.canonical_path.as_ref().unwrap() == &canonical_caller.unwrap()
+            if canonical_caller
+                .map(|canonical_caller| canonical_path == &canonical_caller)
+                .unwrap_or(false)
             {
                 return true;
             }
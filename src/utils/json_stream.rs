@@ -0,0 +1,162 @@
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use std::collections::VecDeque;
+
+/// Splits a byte stream into JSON values as they complete, so that messages arriving in several
+/// reads or several messages arriving in one read (e.g. a notification followed by a response)
+/// are all kept
+///
+/// Only braces, brackets, quotes and backslashes are tracked, which are all ASCII and hence never
+/// part of multi-byte UTF-8 characters.
+#[derive(Default, Debug)]
+pub struct JsonStreamDecoder {
+    buffer: Vec<u8>,
+    // scanning state of buffer[..scanned]
+    scanned: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    messages: VecDeque<String>,
+}
+
+impl JsonStreamDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        // start of the first byte not belonging to a complete message
+        let mut consumed = 0;
+        for i in self.scanned..self.buffer.len() {
+            let byte = self.buffer[i];
+            if self.depth == 0 {
+                match byte {
+                    b'{' | b'[' => {
+                        self.depth = 1;
+                        consumed = i;
+                    }
+                    _ if byte.is_ascii_whitespace() => consumed = i + 1,
+                    _ => {
+                        warn!("Skipping unexpected byte {:#04x} between messages", byte);
+                        consumed = i + 1;
+                    }
+                }
+                continue;
+            }
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        match String::from_utf8(self.buffer[consumed..=i].to_vec()) {
+                            Ok(message) => {
+                                debug!("RECV: {}", message);
+                                self.messages.push_back(message);
+                            }
+                            Err(e) => {
+                                warn!("Discarding message which is not valid UTF-8: {}", e);
+                            }
+                        }
+                        consumed = i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if self.depth == 0 {
+            consumed = self.buffer.len();
+        }
+        self.buffer.drain(..consumed);
+        self.scanned = self.buffer.len();
+    }
+
+    pub fn has_messages(&self) -> bool {
+        !self.messages.is_empty()
+    }
+
+    pub fn next_message(&mut self) -> Option<String> {
+        self.messages.pop_front()
+    }
+
+    pub fn take_messages(&mut self) -> Vec<String> {
+        self.messages.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_single_json() {
+        let mut decoder = JsonStreamDecoder::new();
+        decoder.feed(br#"{"action":"test-associate"}"#);
+        assert_eq!(
+            decoder.take_messages(),
+            vec![r#"{"action":"test-associate"}"#.to_owned()]
+        );
+        assert!(!decoder.has_messages());
+    }
+
+    #[test]
+    fn test_01_multiple_jsons() {
+        let jsons = [
+            r#"{"action":"database-locked"}"#,
+            r#"{"action":"get-logins","message":"testing"}"#,
+            "\n",
+            r#"{"action":"set-login","message":"testing"}"#,
+        ];
+        let mut decoder = JsonStreamDecoder::new();
+        decoder.feed(jsons.concat().as_bytes());
+        assert_eq!(decoder.take_messages(), vec![jsons[0], jsons[1], jsons[3]]);
+    }
+
+    #[test]
+    fn test_02_escaping_and_split_reads() {
+        let jsons = [
+            r#"{"action":"test-associate","message":"\"\\[}"}"#,
+            r#"[{"action":"get-logins","message":"testing\\]"}]"#,
+            r#"{"name":"bücher ✓","children":[{"name":"{"}]}"#,
+        ];
+        let stream = jsons.concat();
+        // one byte at a time, splitting multi-byte characters as well
+        let mut decoder = JsonStreamDecoder::new();
+        let mut messages = Vec::new();
+        for byte in stream.as_bytes() {
+            decoder.feed(&[*byte]);
+            messages.extend(decoder.next_message());
+        }
+        assert_eq!(messages, jsons);
+
+        let mut decoder = JsonStreamDecoder::new();
+        decoder.feed(&stream.as_bytes()[..10]);
+        assert!(!decoder.has_messages());
+        decoder.feed(&stream.as_bytes()[10..]);
+        assert_eq!(decoder.take_messages(), jsons);
+    }
+
+    #[test]
+    fn test_03_garbage_between_messages() {
+        let mut decoder = JsonStreamDecoder::new();
+        decoder.feed(br#"oops{"action":"database-locked"}}{"action":"database-unlocked"}"#);
+        assert_eq!(
+            decoder.take_messages(),
+            vec![
+                r#"{"action":"database-locked"}"#,
+                r#"{"action":"database-unlocked"}"#
+            ]
+        );
+    }
+}
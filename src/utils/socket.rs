@@ -3,12 +3,14 @@ use anyhow::{anyhow, Result};
 use std::{env, io, path::PathBuf};
 
 pub const KEEPASS_SOCKET_ENVIRONMENT_VARIABLE: &str = "KEEPASSXC_BROWSER_SOCKET_PATH";
+pub const AGENT_SOCKET_ENVIRONMENT_VARIABLE: &str = "GIT_CREDENTIAL_KEEPASSXC_AGENT_SOCKET";
 
 #[cfg(windows)]
 const NAMED_PIPE_CONNECT_TIMEOUT_MS: u32 = 100;
 const KEEPASS_SOCKET_NAME: &str = "org.keepassxc.KeePassXC.BrowserServer";
 // socket name prior to KeePassXC 2.6.0
 const KEEPASS_SOCKET_NAME_LEGACY: &str = "kpxc_server";
+const AGENT_SOCKET_NAME: &str = "agent.sock";
 
 pub fn get_socket_path() -> Result<PathBuf> {
     if let Ok(env_socket_path) = env::var(KEEPASS_SOCKET_ENVIRONMENT_VARIABLE) {
@@ -45,6 +47,20 @@ pub fn get_socket_path() -> Result<PathBuf> {
     }
 }
 
+/// Path of the socket of git-credential-keepassxc agent, which may or may not exist
+pub fn get_agent_socket_path() -> Result<PathBuf> {
+    if let Ok(env_socket_path) = env::var(AGENT_SOCKET_ENVIRONMENT_VARIABLE) {
+        return Ok(PathBuf::from(env_socket_path));
+    }
+    let base_dirs = directories_next::BaseDirs::new()
+        .ok_or_else(|| anyhow!("Failed to initialise base_dirs"))?;
+    // runtime_dir is only available on Linux, and temp_dir may be shared with other users
+    let dir = base_dirs
+        .runtime_dir()
+        .unwrap_or_else(|| base_dirs.data_local_dir());
+    Ok(dir.join(env!("CARGO_BIN_NAME")).join(AGENT_SOCKET_NAME))
+}
+
 trait SocketPath {
     fn get_path(&self) -> Result<PathBuf>;
     fn matches_os(&self) -> bool;
@@ -0,0 +1,156 @@
+//! Streams database-locked and database-unlocked notifications from KeePassXC
+
+use crate::cli;
+use crate::config::Config;
+use crate::keepassxc::messages::KeePassAction;
+use crate::utils::{receive_messages, set_use_agent};
+#[allow(unused_imports)]
+use crate::{debug, error, info, warn};
+use crate::{start_session, verify_caller};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// passed to hooks
+const EVENT_ENVIRONMENT_VARIABLE: &str = "KEEPASSXC_DATABASE_EVENT";
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum DatabaseEvent {
+    Locked,
+    Unlocked,
+}
+
+impl DatabaseEvent {
+    fn from_message(message: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Notification {
+            action: String,
+        }
+        let notification: Notification = serde_json::from_str(message).ok()?;
+        if notification.action == KeePassAction::DatabaseLocked.to_string() {
+            Some(Self::Locked)
+        } else if notification.action == KeePassAction::DatabaseUnlocked.to_string() {
+            Some(Self::Unlocked)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Locked => "locked",
+            Self::Unlocked => "unlocked",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct EventLine {
+    event: DatabaseEvent,
+    time: String,
+}
+
+fn run_hook(hook: &str, event: DatabaseEvent) {
+    #[cfg(unix)]
+    let mut command = {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    };
+    info!("Running hook for {} event", event.as_str());
+    match command
+        .env(EVENT_ENVIRONMENT_VARIABLE, event.as_str())
+        .status()
+    {
+        Ok(status) if !status.success() => {
+            warn!("Hook exited with {}", status);
+        }
+        Err(e) => {
+            warn!("Failed to run hook: {}", e);
+        }
+        _ => {}
+    }
+}
+
+pub fn watch<T: AsRef<Path>>(config_path: T, args: &cli::SubWatchArgs) -> Result<()> {
+    let config = Config::read_from(config_path.as_ref())?;
+    verify_caller(&config)?;
+    // the agent doesn't relay notifications
+    set_use_agent(false);
+    let _ = start_session()?;
+    info!("Watching for database events");
+
+    let mut last_event = None;
+    loop {
+        for message in receive_messages()? {
+            let event = match DatabaseEvent::from_message(&message) {
+                Some(event) => event,
+                None => {
+                    debug!("Ignoring {}", message);
+                    continue;
+                }
+            };
+            // e.g. KeePassXC sends database-locked for each open database
+            if last_event == Some(event) {
+                continue;
+            }
+            last_event = Some(event);
+
+            let line = if args.json {
+                serde_json::to_string(&EventLine {
+                    event,
+                    time: OffsetDateTime::now_utc().format(&Rfc3339)?,
+                })?
+            } else {
+                event.as_str().to_owned()
+            };
+            let mut stdout = io::stdout();
+            stdout.write_all(format!("{}\n", line).as_bytes())?;
+            stdout.flush()?;
+
+            if let Some(ref hook) = args.hook {
+                run_hook(hook, event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_00_database_events() {
+        assert_eq!(
+            DatabaseEvent::from_message(r#"{"action":"database-locked"}"#),
+            Some(DatabaseEvent::Locked)
+        );
+        assert_eq!(
+            DatabaseEvent::from_message(r#"{"action":"database-unlocked"}"#),
+            Some(DatabaseEvent::Unlocked)
+        );
+        assert_eq!(
+            DatabaseEvent::from_message(r#"{"action":"get-logins","message":"..."}"#),
+            None
+        );
+        assert_eq!(
+            serde_json::to_string(&EventLine {
+                event: DatabaseEvent::Locked,
+                time: "2023-11-14T22:13:20Z".to_owned(),
+            })
+            .unwrap(),
+            r#"{"event":"locked","time":"2023-11-14T22:13:20Z"}"#
+        );
+    }
+}